@@ -0,0 +1,160 @@
+use crate::exporter::Config;
+use crate::softether_reader::SoftEtherReader;
+use std::io::{self, Write};
+use std::path::Path;
+
+// -------------------------------------------------------------------------------------------------
+// Check
+// -------------------------------------------------------------------------------------------------
+
+// Validate the config file and run StatusGet / SessionList against each hub
+pub struct Check {
+    items: Vec<CheckItem>,
+}
+
+struct CheckItem {
+    check: &'static str,
+    target: String,
+    result: Result<(), String>,
+}
+
+impl Check {
+    pub fn run(config_file: &Path) -> Check {
+        let mut check = Check { items: Vec::new() };
+
+        let (config, unknown) = match Config::from_file_with_unknown(config_file) {
+            Ok(x) => x,
+            Err(x) => {
+                check.push("config", &config_file.display(), Err(x.to_string()));
+                return check;
+            }
+        };
+        check.push(
+            "config",
+            &config_file.display(),
+            config.validate().map_err(|x| x.to_string()),
+        );
+        for key in unknown {
+            check.push("unknown key", &key, Err(String::from("unknown key")));
+        }
+
+        if let Err(x) = config.password() {
+            check.push("secret", &"server", Err(x.to_string()));
+        }
+
+        let vpncmd = config.vpncmd();
+        match SoftEtherReader::resolve_vpncmd(&vpncmd) {
+            Some(x) => check.push("vpncmd", &x.display(), Ok(())),
+            None => check.push("vpncmd", &vpncmd, Err(String::from("not found"))),
+        }
+
+        let server = config.server();
+        for hub in config.hubs() {
+            let password = match hub.password() {
+                Ok(x) => x,
+                Err(x) => {
+                    check.push("secret", &hub.name(), Err(x.to_string()));
+                    continue;
+                }
+            };
+            let status = SoftEtherReader::hub_status(&vpncmd, &server, hub.name(), &password);
+            check.push(
+                "StatusGet",
+                &hub.name(),
+                status.map(|_| ()).map_err(|x| x.to_string()),
+            );
+            let sessions = SoftEtherReader::hub_sessions(&vpncmd, &server, hub.name(), &password);
+            check.push(
+                "SessionList",
+                &hub.name(),
+                sessions.map(|_| ()).map_err(|x| x.to_string()),
+            );
+        }
+
+        check
+    }
+
+    pub fn passed(&self) -> bool {
+        self.items.iter().all(|x| x.result.is_ok())
+    }
+
+    pub fn print<T: Write>(&self, w: &mut T) -> Result<(), io::Error> {
+        let check_width = self.items.iter().map(|x| x.check.len()).max().unwrap_or(0);
+        let target_width = self.items.iter().map(|x| x.target.len()).max().unwrap_or(0);
+        for item in &self.items {
+            let result = match &item.result {
+                Ok(_) => String::from("pass"),
+                Err(x) => format!("fail ( {} )", x.trim()),
+            };
+            writeln!(
+                w,
+                "{:check_width$}  {:target_width$}  {}",
+                item.check,
+                item.target,
+                result,
+                check_width = check_width,
+                target_width = target_width
+            )?;
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, check: &'static str, target: &dyn ToString, result: Result<(), String>) {
+        self.items.push(CheckItem {
+            check,
+            target: target.to_string(),
+            result,
+        });
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Test
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs::{self, File};
+
+    #[test]
+    fn test_check() {
+        let path = env::temp_dir().join("softether_exporter_check.toml");
+        let mut file = File::create(&path).unwrap();
+        file.write_all(
+            b"vpncmd = \"/nonexistent/vpncmd\"\n\
+              [[hubs]]\nname = \"HUB1\"\npasswd = \"xxx\"\n\
+              [[hubs]]\nname = \"HUB2\"\npassword_file = \"/nonexistent/password\"\n",
+        )
+        .unwrap();
+
+        let check = Check::run(&path);
+        fs::remove_file(&path).unwrap();
+
+        let mut buf = Vec::new();
+        check.print(&mut buf).unwrap();
+        let lines: Vec<_> = String::from_utf8(buf)
+            .unwrap()
+            .lines()
+            .map(|x| x.split_whitespace().take(3).collect::<Vec<_>>().join(" "))
+            .collect();
+
+        assert!(!check.passed());
+        assert_eq!(lines.len(), 6);
+        // Config validation fails because of the unreadable password_file
+        assert!(lines[0].starts_with("config") && lines[0].ends_with("fail"));
+        assert_eq!(lines[1], "unknown key hubs.0.passwd");
+        assert_eq!(lines[2], "vpncmd /nonexistent/vpncmd fail");
+        assert_eq!(lines[3], "StatusGet HUB1 fail");
+        assert_eq!(lines[4], "SessionList HUB1 fail");
+        assert_eq!(lines[5], "secret HUB2 fail");
+    }
+
+    #[test]
+    fn test_check_invalid_config() {
+        let check = Check::run(Path::new("/nonexistent/config.toml"));
+        assert!(!check.passed());
+        assert_eq!(check.items.len(), 1);
+    }
+}
@@ -1,18 +1,38 @@
-use crate::softether_reader::SoftEtherReader;
-use anyhow::Error;
-use hyper::header::ContentType;
-use hyper::mime::{Mime, SubLevel, TopLevel};
-use hyper::server::{Request, Response, Server};
-use hyper::uri::RequestUri;
+use crate::openmetrics::{self, OpenMetricsEncoder, OPENMETRICS_FORMAT};
+use crate::push::{PushConfig, Pusher};
+use crate::server::{self, Body};
+use crate::session_event::{self, EventKind};
+use crate::sink::Snapshot;
+use crate::softether_reader::{HubSession, HubStatus, SessionProtocol, SoftEtherReader};
+use crate::systemd;
+use crate::web_config::WebConfig;
+use anyhow::{anyhow, Error};
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
+use chrono_tz::Tz;
+use hyper::body::Incoming;
+use hyper::header::{HeaderValue, ACCEPT, AUTHORIZATION, WWW_AUTHENTICATE};
+use hyper::{Method, Request, Response, StatusCode};
 use lazy_static::lazy_static;
-use prometheus;
-use prometheus::{register_gauge_vec, Encoder, GaugeVec, TextEncoder};
+use log::{error, info, warn};
+use prometheus::core::{Collector, MetricVec, MetricVecBuilder};
+use prometheus::proto::MetricFamily;
+use prometheus::{
+    register_gauge, register_gauge_vec, register_int_counter_vec, Encoder, Gauge, GaugeVec,
+    IntCounterVec, TextEncoder,
+};
 use serde::Deserialize;
 use std::collections::HashMap;
-use std::fs::File;
-use std::io::Read;
-use std::path::Path;
-use toml;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
 
 lazy_static! {
     static ref UP: GaugeVec =
@@ -99,6 +119,16 @@ lazy_static! {
         &["hub"]
     )
     .unwrap();
+    static ref CONFIG_LAST_RELOAD_SUCCESSFUL: Gauge = register_gauge!(
+        "softether_config_last_reload_successful",
+        "The last configuration reload attempt was successful."
+    )
+    .unwrap();
+    static ref CONFIG_LAST_RELOAD_SUCCESS_TIMESTAMP: Gauge = register_gauge!(
+        "softether_config_last_reload_success_timestamp_seconds",
+        "Timestamp of the last successful configuration reload."
+    )
+    .unwrap();
     static ref BUILD_INFO: GaugeVec = register_gauge_vec!(
         "softether_build_info",
         "A metric with a constant '1' value labeled by version, revision and rustversion",
@@ -117,31 +147,217 @@ lazy_static! {
         &["hub", "user"]
     )
     .unwrap();
+    static ref SCRAPE_ERRORS: IntCounterVec = register_int_counter_vec!(
+        "softether_scrape_errors_total",
+        "Number of vpncmd errors by kind.",
+        &["hub", "kind"]
+    )
+    .unwrap();
+    static ref SESSION_CONNECTS: IntCounterVec = register_int_counter_vec!(
+        "softether_session_connects_total",
+        "Number of session logins detected by the difference of SessionList between collections.",
+        &["hub", "user"]
+    )
+    .unwrap();
+    static ref SESSION_DISCONNECTS: IntCounterVec = register_int_counter_vec!(
+        "softether_session_disconnects_total",
+        "Number of session logouts detected by the difference of SessionList between collections.",
+        &["hub", "user"]
+    )
+    .unwrap();
+    static ref PARSE_MISSING_FIELDS: GaugeVec = register_gauge_vec!(
+        "softether_parse_missing_fields",
+        "Number of expected fields which are missing in vpncmd output.",
+        &["hub", "command"]
+    )
+    .unwrap();
+    static ref PARSE_UNKNOWN_FIELDS: GaugeVec = register_gauge_vec!(
+        "softether_parse_unknown_fields",
+        "Number of unknown fields in vpncmd output.",
+        &["hub", "command"]
+    )
+    .unwrap();
+    static ref SESSIONS_BY_PROTOCOL: GaugeVec = register_gauge_vec!(
+        "softether_sessions_by_protocol",
+        "Number of sessions by connection protocol.",
+        &["hub", "protocol"]
+    )
+    .unwrap();
+    static ref HUB_INFO: GaugeVec = register_gauge_vec!(
+        "softether_hub_info",
+        "A metric with a constant '1' value labeled by hub type",
+        &["hub", "type"]
+    )
+    .unwrap();
+    static ref HUB_INFO_LABELS: Mutex<HashMap<String, &'static str>> = Mutex::new(HashMap::new());
+    static ref LAST_LOGIN_TIMESTAMP: GaugeVec = register_gauge_vec!(
+        "softether_last_login_timestamp_seconds",
+        "Last login time in unix epoch seconds.",
+        &["hub"]
+    )
+    .unwrap();
+    static ref LAST_COMMUNICATION_TIMESTAMP: GaugeVec = register_gauge_vec!(
+        "softether_last_communication_timestamp_seconds",
+        "Last communication time in unix epoch seconds.",
+        &["hub"]
+    )
+    .unwrap();
+    static ref CREATED_TIMESTAMP: GaugeVec = register_gauge_vec!(
+        "softether_created_timestamp_seconds",
+        "Hub creation time in unix epoch seconds.",
+        &["hub"]
+    )
+    .unwrap();
+    static ref ACCESS_LISTS: GaugeVec = register_gauge_vec!(
+        "softether_access_lists",
+        "Number of access list rules.",
+        &["hub"]
+    )
+    .unwrap();
+    static ref TRUSTED_CAS: GaugeVec = register_gauge_vec!(
+        "softether_trusted_cas",
+        "Number of trusted CA certificates.",
+        &["hub"]
+    )
+    .unwrap();
+    static ref CRLS: GaugeVec = register_gauge_vec!(
+        "softether_crls",
+        "Number of certificate revocation list entries.",
+        &["hub"]
+    )
+    .unwrap();
+    static ref HUB_CONFIG_INFO: GaugeVec = register_gauge_vec!(
+        "softether_hub_config_info",
+        "A metric with a constant '1' value labeled by hub type and security-relevant settings",
+        &[
+            "hub",
+            "type",
+            "anonymous_enum",
+            "radius",
+            "security_log",
+            "packet_log"
+        ]
+    )
+    .unwrap();
+    static ref HUB_CONFIG_LABELS: Mutex<HashMap<String, Vec<String>>> = Mutex::new(HashMap::new());
+    static ref HUB_CONFIG_COLLECTED: Mutex<HashMap<String, Instant>> = Mutex::new(HashMap::new());
+    static ref HUB_SNAPSHOTS: Mutex<HashMap<String, (HubStatus, Vec<HubSession>)>> =
+        Mutex::new(HashMap::new());
+    static ref HUB_STATES: Mutex<HashMap<String, HubState>> = Mutex::new(HashMap::new());
+    // Sessions of the last successful collection, which are kept even if a collection fails
+    static ref LAST_SESSIONS: Mutex<HashMap<String, Vec<HubSession>>> = Mutex::new(HashMap::new());
+    static ref IN_FLIGHT: Mutex<HashMap<u64, Instant>> = Mutex::new(HashMap::new());
+    static ref COLLECTION: Arc<tokio::sync::Mutex<()>> = Arc::new(tokio::sync::Mutex::new(()));
+    static ref COLLECTED_CONFIG: Mutex<Option<Config>> = Mutex::new(None);
+    static ref PROTOCOL_ENABLED: GaugeVec = register_gauge_vec!(
+        "softether_protocol_enabled",
+        "VPN protocol server function enabled.",
+        &["protocol"]
+    )
+    .unwrap();
+    static ref ETHERIP_CLIENTS: GaugeVec = register_gauge_vec!(
+        "softether_etherip_clients",
+        "Number of EtherIP / L2TPv3 client settings.",
+        &["hub"]
+    )
+    .unwrap();
+    static ref DDNS_REGISTERED: Gauge = register_gauge!(
+        "softether_ddns_registered",
+        "Dynamic DNS hostname is registered."
+    )
+    .unwrap();
+    static ref DDNS_INFO: GaugeVec = register_gauge_vec!(
+        "softether_ddns_info",
+        "A metric with a constant '1' value labeled by dynamic DNS hostname and global addresses",
+        &["hostname", "ipv4", "ipv6"]
+    )
+    .unwrap();
+    static ref AZURE_ENABLED: Gauge =
+        register_gauge!("softether_azure_enabled", "VPN Azure function enabled.").unwrap();
+    static ref AZURE_CONNECTED: Gauge = register_gauge!(
+        "softether_azure_connected",
+        "Connection to VPN Azure cloud server is established."
+    )
+    .unwrap();
+    static ref AZURE_INFO: GaugeVec = register_gauge_vec!(
+        "softether_azure_info",
+        "A metric with a constant '1' value labeled by VPN Azure hostname",
+        &["hostname"]
+    )
+    .unwrap();
 }
 
-static LANDING_PAGE: &'static str = "<html>
-<head><title>SoftEther Exporter</title></head>
-<body>
-<h1>SoftEther Exporter</h1>
-<p><a href=\"/metrics\">Metrics</a></p>
-</body>
-";
-
-static VERSION: &'static str = env!("CARGO_PKG_VERSION");
+static VERSION: &str = env!("CARGO_PKG_VERSION");
 static GIT_REVISION: Option<&'static str> = option_env!("GIT_REVISION");
 static RUST_VERSION: Option<&'static str> = option_env!("RUST_VERSION");
+static RELOAD_POLL_INTERVAL: u64 = 5;
+// Number of finished collections, and COLLECTED_CONFIG is the config of the last one
+static COLLECTED: AtomicU64 = AtomicU64::new(0);
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone, PartialEq)]
 pub struct Config {
     vpncmd: Option<String>,
     server: Option<String>,
+    password: Option<String>,
+    password_file: Option<PathBuf>,
+    timezone: Option<String>,
+    strict: Option<Strict>,
     hubs: Vec<Hub>,
+    event_log: Option<PathBuf>,
+    config_interval: Option<u64>,
+    push: Option<PushConfig>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Strict {
+    /// Ignore unknown and missing fields
+    Off,
+    /// Export the number of unknown and missing fields
+    Report,
+    /// Export the number of unknown and missing fields, and fail the hub if fields are missing
+    Fail,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
 pub struct Hub {
     name: Option<String>,
     password: Option<String>,
+    password_file: Option<PathBuf>,
+}
+
+impl Hub {
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or("")
+    }
+
+    pub fn password(&self) -> Result<String, Error> {
+        Ok(secret(&self.password, &self.password_file)?.unwrap_or_default())
+    }
+}
+
+// The password is read from `password_file` if specified, so that it can be kept out of the config file
+fn secret(
+    password: &Option<String>,
+    password_file: &Option<PathBuf>,
+) -> Result<Option<String>, Error> {
+    match (password, password_file) {
+        (Some(_), Some(_)) => Err(anyhow!("both password and password_file are specified")),
+        (_, Some(file)) => {
+            let s = fs::read_to_string(file)
+                .map_err(|x| anyhow!("failed to read {}: {}", file.display(), x))?;
+            Ok(Some(String::from(s.trim_end_matches(&['\r', '\n'][..]))))
+        }
+        (password, None) => Ok(password.clone()),
+    }
+}
+
+// Hub names and vpncmd error messages are embedded in the landing page
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
 }
 
 impl Config {
@@ -152,146 +368,1644 @@ impl Config {
         let config: Config = toml::from_str(&s)?;
         Ok(config)
     }
+
+    // Same as from_file, but also returns the keys which are not known by Config
+    pub fn from_file_with_unknown(file: &Path) -> Result<(Config, Vec<String>), Error> {
+        let s = fs::read_to_string(file)?;
+        let mut unknown = Vec::new();
+        let config: Config = serde_ignored::deserialize(toml::Deserializer::parse(&s)?, |x| {
+            unknown.push(x.to_string())
+        })?;
+        Ok((config, unknown))
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        self.timezone()?;
+        self.password()?;
+        for hub in &self.hubs {
+            if hub.name().is_empty() {
+                return Err(anyhow!("hub name is not specified"));
+            }
+            hub.password()
+                .map_err(|x| anyhow!("hub {}: {}", hub.name(), x))?;
+        }
+        if let Some(ref push) = self.push {
+            push.validate()?;
+        }
+        Ok(())
+    }
+
+    pub fn vpncmd(&self) -> String {
+        self.vpncmd.clone().unwrap_or(String::from("vpncmd"))
+    }
+
+    pub fn server(&self) -> String {
+        self.server.clone().unwrap_or(String::from("localhost"))
+    }
+
+    pub fn password(&self) -> Result<Option<String>, Error> {
+        secret(&self.password, &self.password_file)
+    }
+
+    pub fn hubs(&self) -> &[Hub] {
+        &self.hubs
+    }
+
+    // Hub config is collected less often than the status, because it takes 5 vpncmd commands per hub
+    fn config_interval(&self) -> Duration {
+        Duration::from_secs(self.config_interval.unwrap_or(300))
+    }
+
+    fn timezone(&self) -> Result<Option<Tz>, Error> {
+        match self.timezone {
+            Some(ref x) => Ok(Some(
+                x.parse::<Tz>()
+                    .map_err(|x| anyhow!("invalid timezone: {}", x))?,
+            )),
+            None => Ok(None),
+        }
+    }
 }
 
 pub struct Exporter;
 
+// Result of the last collection of each hub
+#[derive(Clone, Debug)]
+struct HubState {
+    error: Option<String>,
+    duration: Duration,
+    timestamp: SystemTime,
+}
+
+// In-flight collection, which is tracked to detect a stalled vpncmd by the watchdog
+struct InFlight(u64);
+
+impl InFlight {
+    fn new() -> InFlight {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        IN_FLIGHT.lock().unwrap().insert(id, Instant::now());
+        InFlight(id)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        IN_FLIGHT.lock().unwrap().remove(&self.0);
+    }
+}
+
+struct State {
+    config: Arc<RwLock<Config>>,
+    config_file: PathBuf,
+    web_config: WebConfig,
+    encoder: TextEncoder,
+    openmetrics_encoder: OpenMetricsEncoder,
+}
+
 impl Exporter {
-    pub fn start(config: Config, listen_address: &str, _verbose: bool) -> Result<(), Error> {
-        let encoder = TextEncoder::new();
-        let vpncmd = config.vpncmd.unwrap_or(String::from("vpncmd"));
-        let server = config.server.unwrap_or(String::from("localhost"));
-        let hubs = config.hubs;
+    pub fn start(
+        config: Config,
+        config_file: &Path,
+        listen_address: &str,
+        web_config: WebConfig,
+        request_timeout: Duration,
+    ) -> Result<(), Error> {
+        config.validate()?;
+        Exporter::register();
+        if SoftEtherReader::resolve_vpncmd(&config.vpncmd()).is_none() {
+            return Err(anyhow!("vpncmd is not found: {}", config.vpncmd()));
+        }
+        Exporter::reloaded(true);
 
-        let addr = if listen_address.starts_with(":") {
+        let addr = if listen_address.starts_with(':') {
             format!("0.0.0.0{}", listen_address)
         } else {
             String::from(listen_address)
         };
+        let tls = web_config.tls_acceptor()?;
 
-        println!("Server started: {}", addr);
-
-        Server::http(addr)?.handle(move |req: Request, mut res: Response| {
-            if req.uri == RequestUri::AbsolutePath("/metrics".to_string()) {
-                for hub in hubs.clone() {
-                    let name = hub.name.unwrap_or(String::from(""));
-                    let password = hub.password.unwrap_or(String::from(""));
-                    let status =
-                        match SoftEtherReader::hub_status(&vpncmd, &server, &name, &password) {
-                            Ok(x) => x,
-                            Err(x) => {
-                                UP.with_label_values(&[&name]).set(0.0);
-                                println!("Hub status read failed: {}", x);
-                                continue;
-                            }
-                        };
-
-                    let sessions =
-                        match SoftEtherReader::hub_sessions(&vpncmd, &server, &name, &password) {
-                            Ok(x) => x,
-                            Err(x) => {
-                                UP.with_label_values(&[&name]).set(0.0);
-                                println!("Hub sessions read failed: {}", x);
-                                continue;
-                            }
-                        };
-
-                    UP.with_label_values(&[&status.name]).set(1.0);
-                    ONLINE
-                        .with_label_values(&[&status.name])
-                        .set(if status.online { 1.0 } else { 0.0 });
-                    SESSIONS
-                        .with_label_values(&[&status.name])
-                        .set(status.sessions);
-                    SESSIONS_CLIENT
-                        .with_label_values(&[&status.name])
-                        .set(status.sessions_client);
-                    SESSIONS_BRIDGE
-                        .with_label_values(&[&status.name])
-                        .set(status.sessions_bridge);
-                    USERS.with_label_values(&[&status.name]).set(status.users);
-                    GROUPS.with_label_values(&[&status.name]).set(status.groups);
-                    MAC_TABLES
-                        .with_label_values(&[&status.name])
-                        .set(status.mac_tables);
-                    IP_TABLES
-                        .with_label_values(&[&status.name])
-                        .set(status.ip_tables);
-                    LOGINS.with_label_values(&[&status.name]).set(status.logins);
-                    OUTGOING_UNICAST_PACKETS
-                        .with_label_values(&[&status.name])
-                        .set(status.outgoing_unicast_packets);
-                    OUTGOING_UNICAST_BYTES
-                        .with_label_values(&[&status.name])
-                        .set(status.outgoing_unicast_bytes);
-                    OUTGOING_BROADCAST_PACKETS
-                        .with_label_values(&[&status.name])
-                        .set(status.outgoing_broadcast_packets);
-                    OUTGOING_BROADCAST_BYTES
-                        .with_label_values(&[&status.name])
-                        .set(status.outgoing_broadcast_bytes);
-                    INCOMING_UNICAST_PACKETS
-                        .with_label_values(&[&status.name])
-                        .set(status.incoming_unicast_packets);
-                    INCOMING_UNICAST_BYTES
-                        .with_label_values(&[&status.name])
-                        .set(status.incoming_unicast_bytes);
-                    INCOMING_BROADCAST_PACKETS
-                        .with_label_values(&[&status.name])
-                        .set(status.incoming_broadcast_packets);
-                    INCOMING_BROADCAST_BYTES
+        let state = Arc::new(State {
+            config: Arc::new(RwLock::new(config)),
+            config_file: config_file.to_path_buf(),
+            web_config,
+            encoder: TextEncoder::new(),
+            openmetrics_encoder: OpenMetricsEncoder::new(Exporter::now()),
+        });
+
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?;
+        let ret = runtime.block_on(async {
+            #[cfg(unix)]
+            Exporter::reload_on_sighup(state.config.clone(), state.config_file.clone())?;
+            Exporter::reload_on_change(state.config.clone(), state.config_file.clone());
+
+            // The socket passed by systemd socket activation is used instead of listen_address
+            let listener = match systemd::listener()? {
+                Some(x) => TcpListener::from_std(x)?,
+                None => TcpListener::bind(&addr).await?,
+            };
+            let scheme = if tls.is_some() { "https" } else { "http" };
+            info!("Server started: {}://{}", scheme, listener.local_addr()?);
+            systemd::notify_ready();
+            if let Some(timeout) = systemd::watchdog_timeout() {
+                Exporter::ping_watchdog(timeout);
+            }
+
+            // The first collection is run in background, so that /-/ready becomes ready without a scrape
+            let config = state.config.read().unwrap().clone();
+            tokio::task::spawn_blocking(move || Exporter::collect(&config));
+
+            let handler = move |req| Exporter::handle(state.clone(), req);
+            server::serve(listener, tls, request_timeout, handler, async {
+                server::shutdown_signal().await;
+                systemd::notify_stopping();
+            })
+            .await?;
+
+            info!("Server stopped");
+            Ok(())
+        });
+        // vpncmd running in blocking threads is not waited
+        runtime.shutdown_timeout(Duration::from_secs(1));
+        ret
+    }
+
+    async fn handle(state: Arc<State>, req: Request<Incoming>) -> Response<Body> {
+        let authorization = req
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|x| x.to_str().ok())
+            .map(String::from);
+        let path = req.uri().path();
+        let get = req.method() == Method::GET || req.method() == Method::HEAD;
+
+        // Health checks are allowed without authentication for load balancers
+        if get && path == "/-/healthy" {
+            return server::response(StatusCode::OK, "text/plain", "Healthy\n");
+        } else if get && path == "/-/ready" {
+            let ret = tokio::task::spawn_blocking(move || {
+                let config = state.config.read().unwrap().clone();
+                Exporter::ready(&config)
+            })
+            .await
+            .unwrap_or_else(|x| Err(x.to_string()));
+            return match ret {
+                Ok(_) => server::response(StatusCode::OK, "text/plain", "Ready\n"),
+                Err(x) => server::response(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "text/plain",
+                    format!("Not ready: {}\n", x),
+                ),
+            };
+        }
+
+        // bcrypt verification is CPU-bound, so it is run in a blocking thread
+        let authorized = {
+            let state = state.clone();
+            tokio::task::spawn_blocking(move || {
+                state.web_config.authorized(authorization.as_deref())
+            })
+            .await
+            .unwrap_or(false)
+        };
+        if !authorized {
+            let mut res =
+                server::response(StatusCode::UNAUTHORIZED, "text/plain", "Unauthorized\n");
+            res.headers_mut()
+                .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Basic"));
+            return res;
+        }
+
+        match path {
+            "/metrics" if get => {
+                let accept = req
+                    .headers()
+                    .get_all(ACCEPT)
+                    .iter()
+                    .filter_map(|x| x.to_str().ok())
+                    .collect::<Vec<_>>()
+                    .join(",");
+                let config = state.config.read().unwrap().clone();
+                Exporter::collect_async(config).await;
+                let ret = tokio::task::spawn_blocking(move || {
+                    let metric_familys = Exporter::gather(|_| true);
+                    let mut buffer = vec![];
+                    let format_type = if openmetrics::accepts_openmetrics(&accept) {
+                        state
+                            .openmetrics_encoder
+                            .encode(&metric_familys, &mut buffer)?;
+                        OPENMETRICS_FORMAT
+                    } else {
+                        state.encoder.encode(&metric_familys, &mut buffer)?;
+                        state.encoder.format_type()
+                    };
+                    Ok::<_, Error>(server::response(StatusCode::OK, format_type, buffer))
+                })
+                .await;
+                match ret {
+                    Ok(Ok(x)) => x,
+                    Ok(Err(x)) => server::response(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "text/plain",
+                        format!("Metrics encode failed: {}\n", x),
+                    ),
+                    Err(x) => server::response(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "text/plain",
+                        format!("Metrics collection failed: {}\n", x),
+                    ),
+                }
+            }
+            "/" if get => {
+                let config = state.config.read().unwrap();
+                server::response(StatusCode::OK, "text/html", Exporter::landing_page(&config))
+            }
+            "/-/reload" if req.method() == Method::POST => {
+                let ret = Exporter::reload_async(state.config.clone(), state.config_file.clone());
+                match ret.await {
+                    Ok(_) => server::response(StatusCode::OK, "text/plain", "Config reloaded\n"),
+                    Err(x) => server::response(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "text/plain",
+                        format!("Config reload failed: {}\n", x),
+                    ),
+                }
+            }
+            "/metrics" | "/" | "/-/reload" | "/-/healthy" | "/-/ready" => server::response(
+                StatusCode::METHOD_NOT_ALLOWED,
+                "text/plain",
+                "Method not allowed\n",
+            ),
+            x if get && x.starts_with("/api/v1/hubs") => {
+                let config = state.config.read().unwrap();
+                match Exporter::api(&config, x) {
+                    Some(x) => server::response(StatusCode::OK, "application/json", x),
+                    None => server::response(StatusCode::NOT_FOUND, "text/plain", "Not found\n"),
+                }
+            }
+            _ => server::response(StatusCode::NOT_FOUND, "text/plain", "Not found\n"),
+        }
+    }
+
+    // Collect once and write to the file for the textfile collector of node_exporter
+    // The output is written to a temporary file and renamed, so that a partial file is never read
+    pub fn collect_once(config: Config, output: &Path) -> Result<(), Error> {
+        config.validate()?;
+        Exporter::register();
+        Exporter::collect(&config);
+        // process_* metrics of a short-lived process are meaningless, and conflict with node_exporter's own
+        let metric_familys = Exporter::gather(|x| !x.starts_with("process_"));
+        let mut buffer = vec![];
+        TextEncoder::new().encode(&metric_familys, &mut buffer)?;
+
+        let mut tmp = output.as_os_str().to_owned();
+        tmp.push(format!(".{}.tmp", process::id()));
+        let tmp = PathBuf::from(tmp);
+
+        let ret = File::create(&tmp)
+            .and_then(|mut f| {
+                f.write_all(&buffer)?;
+                f.sync_all()
+            })
+            .and_then(|_| fs::rename(&tmp, output));
+        if ret.is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+        ret.map_err(|x| anyhow!("failed to write {}: {}", output.display(), x))
+    }
+
+    // Collect periodically and push to Pushgateway, remote_write, InfluxDB, Graphite or StatsD, for the server
+    // behind NAT which Prometheus can't reach, or for the monitoring system other than Prometheus.
+    // The watchdog is pinged after each collection.
+    pub fn push(config: Config) -> Result<(), Error> {
+        config.validate()?;
+        Exporter::register();
+        let push = config
+            .push
+            .clone()
+            .ok_or_else(|| anyhow!("[push] is not found in the config file"))?;
+        let mut pusher = Pusher::new(&push, &config.server());
+        let interval = push.interval();
+        Exporter::reloaded(true);
+        systemd::notify_ready();
+        info!("Push started: every {}s", interval.as_secs());
+
+        loop {
+            let start = Instant::now();
+            Exporter::collect(&config);
+            let metric_familys = Exporter::gather(|_| true);
+            let snapshots = Exporter::snapshots(&config);
+            pusher.push(
+                &metric_familys,
+                &snapshots,
+                (Exporter::now() * 1000.0) as i64,
+                start + interval,
+            );
+            systemd::notify_watchdog();
+            if let Some(x) = interval.checked_sub(start.elapsed()) {
+                thread::sleep(x);
+            }
+        }
+    }
+
+    // Metrics are registered lazily, so they are registered at the startup.
+    // A registration error ( e.g. a duplicate name ) panics here, not at the first scrape.
+    fn register() {
+        lazy_static::initialize(&UP);
+        lazy_static::initialize(&ONLINE);
+        lazy_static::initialize(&SESSIONS);
+        lazy_static::initialize(&SESSIONS_CLIENT);
+        lazy_static::initialize(&SESSIONS_BRIDGE);
+        lazy_static::initialize(&USERS);
+        lazy_static::initialize(&GROUPS);
+        lazy_static::initialize(&MAC_TABLES);
+        lazy_static::initialize(&IP_TABLES);
+        lazy_static::initialize(&LOGINS);
+        lazy_static::initialize(&OUTGOING_UNICAST_PACKETS);
+        lazy_static::initialize(&OUTGOING_UNICAST_BYTES);
+        lazy_static::initialize(&OUTGOING_BROADCAST_PACKETS);
+        lazy_static::initialize(&OUTGOING_BROADCAST_BYTES);
+        lazy_static::initialize(&INCOMING_UNICAST_PACKETS);
+        lazy_static::initialize(&INCOMING_UNICAST_BYTES);
+        lazy_static::initialize(&INCOMING_BROADCAST_PACKETS);
+        lazy_static::initialize(&INCOMING_BROADCAST_BYTES);
+        lazy_static::initialize(&USER_TRANSFER_BYTES);
+        lazy_static::initialize(&USER_TRANSFER_PACKETS);
+        lazy_static::initialize(&PARSE_MISSING_FIELDS);
+        lazy_static::initialize(&PARSE_UNKNOWN_FIELDS);
+        lazy_static::initialize(&SESSIONS_BY_PROTOCOL);
+        lazy_static::initialize(&HUB_INFO);
+        lazy_static::initialize(&LAST_LOGIN_TIMESTAMP);
+        lazy_static::initialize(&LAST_COMMUNICATION_TIMESTAMP);
+        lazy_static::initialize(&CREATED_TIMESTAMP);
+        lazy_static::initialize(&ACCESS_LISTS);
+        lazy_static::initialize(&TRUSTED_CAS);
+        lazy_static::initialize(&CRLS);
+        lazy_static::initialize(&HUB_CONFIG_INFO);
+        lazy_static::initialize(&PROTOCOL_ENABLED);
+        lazy_static::initialize(&ETHERIP_CLIENTS);
+        lazy_static::initialize(&DDNS_INFO);
+        lazy_static::initialize(&AZURE_INFO);
+        lazy_static::initialize(&BUILD_INFO);
+        lazy_static::initialize(&CONFIG_LAST_RELOAD_SUCCESSFUL);
+        lazy_static::initialize(&CONFIG_LAST_RELOAD_SUCCESS_TIMESTAMP);
+        lazy_static::initialize(&DDNS_REGISTERED);
+        lazy_static::initialize(&AZURE_ENABLED);
+        lazy_static::initialize(&AZURE_CONNECTED);
+        lazy_static::initialize(&SCRAPE_ERRORS);
+        lazy_static::initialize(&SESSION_CONNECTS);
+        lazy_static::initialize(&SESSION_DISCONNECTS);
+        SoftEtherReader::register();
+    }
+
+    fn gather<F: Fn(&str) -> bool>(filter: F) -> Vec<MetricFamily> {
+        let git_revision = GIT_REVISION.unwrap_or("");
+        let rust_version = RUST_VERSION.unwrap_or("");
+        BUILD_INFO
+            .with_label_values(&[&VERSION, &git_revision, &rust_version])
+            .set(1.0);
+
+        prometheus::gather()
+            .into_iter()
+            .filter(|x| filter(x.name()))
+            .collect()
+    }
+
+    fn now() -> f64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_secs_f64())
+            .unwrap_or(0.0)
+    }
+
+    // Collection is single-flight. Callers during a running collection wait for it and share the result
+    // if the config is the same, so that concurrent or timed-out scrapes don't pile up vpncmd processes.
+    fn collect(config: &Config) {
+        let generation = COLLECTED.load(Ordering::SeqCst);
+        let _lock = COLLECTION.blocking_lock();
+        if !Exporter::collected_since(config, generation) {
+            Exporter::collect_locked(config);
+        }
+    }
+
+    // The lock is moved into the blocking thread, so that it is held until the collection finishes
+    // even if the request has timed out.
+    async fn collect_async(config: Config) {
+        let generation = COLLECTED.load(Ordering::SeqCst);
+        let lock = COLLECTION.clone().lock_owned().await;
+        if Exporter::collected_since(&config, generation) {
+            return;
+        }
+        let _ = tokio::task::spawn_blocking(move || {
+            let _lock = lock;
+            Exporter::collect_locked(&config);
+        })
+        .await;
+    }
+
+    fn collected_since(config: &Config, generation: u64) -> bool {
+        COLLECTED.load(Ordering::SeqCst) != generation
+            && COLLECTED_CONFIG.lock().unwrap().as_ref() == Some(config)
+    }
+
+    fn collect_locked(config: &Config) {
+        Exporter::collect_hubs(config);
+        *COLLECTED_CONFIG.lock().unwrap() = Some(config.clone());
+        COLLECTED.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn collect_hubs(config: &Config) {
+        let _in_flight = InFlight::new();
+        let vpncmd = config.vpncmd();
+        let server = config.server();
+        let strict = config.strict.unwrap_or(Strict::Off);
+        let timezone = config.timezone().unwrap_or(None);
+        let event_log = config.event_log.as_deref();
+        let config_interval = config.config_interval();
+
+        match config.password() {
+            Ok(Some(password)) => Exporter::collect_server(&vpncmd, &server, &password),
+            Ok(None) => (),
+            Err(x) => warn!("Server password read failed: {}", x),
+        }
+
+        for hub in config.hubs() {
+            let name = String::from(hub.name());
+            let start = Instant::now();
+            let ret = Exporter::collect_hub(
+                &vpncmd,
+                &server,
+                hub,
+                strict,
+                &timezone,
+                event_log,
+                config_interval,
+            );
+            if let Err(ref x) = ret {
+                UP.with_label_values(&[&name]).set(0.0);
+                HUB_SNAPSHOTS.lock().unwrap().remove(&name);
+                warn!("{}", x);
+            }
+            HUB_STATES.lock().unwrap().insert(
+                name,
+                HubState {
+                    error: ret.err(),
+                    duration: start.elapsed(),
+                    timestamp: SystemTime::now(),
+                },
+            );
+        }
+    }
+
+    fn collect_hub(
+        vpncmd: &str,
+        server: &str,
+        hub: &Hub,
+        strict: Strict,
+        timezone: &Option<Tz>,
+        event_log: Option<&Path>,
+        config_interval: Duration,
+    ) -> Result<(), String> {
+        let name = hub.name();
+        let password = match hub.password() {
+            Ok(x) => x,
+            Err(x) => {
+                return Err(format!("Hub password read failed: {}", x));
+            }
+        };
+        let status = match SoftEtherReader::hub_status(vpncmd, server, name, &password) {
+            Ok(x) => x,
+            Err(x) => {
+                SCRAPE_ERRORS.with_label_values(&[name, x.kind()]).inc();
+                return Err(format!("Hub status read failed: {}", x));
+            }
+        };
+
+        if strict != Strict::Off {
+            let report = &status.report;
+            PARSE_MISSING_FIELDS
+                .with_label_values(&[name, report.command])
+                .set(report.missing.len() as f64);
+            PARSE_UNKNOWN_FIELDS
+                .with_label_values(&[name, report.command])
+                .set(report.unknown.len() as f64);
+            if strict == Strict::Fail && !report.missing.is_empty() {
+                SCRAPE_ERRORS.with_label_values(&[name, "parse"]).inc();
+                return Err(format!(
+                    "Hub status parse failed: missing fields {:?}",
+                    report.missing
+                ));
+            }
+        }
+
+        let sessions = match SoftEtherReader::hub_sessions(vpncmd, server, name, &password) {
+            Ok(x) => x,
+            Err(x) => {
+                SCRAPE_ERRORS.with_label_values(&[name, x.kind()]).inc();
+                return Err(format!("Hub sessions read failed: {}", x));
+            }
+        };
+
+        UP.with_label_values(&[&status.name]).set(1.0);
+        ONLINE
+            .with_label_values(&[&status.name])
+            .set(if status.online { 1.0 } else { 0.0 });
+        SESSIONS
+            .with_label_values(&[&status.name])
+            .set(status.sessions);
+        SESSIONS_CLIENT
+            .with_label_values(&[&status.name])
+            .set(status.sessions_client);
+        SESSIONS_BRIDGE
+            .with_label_values(&[&status.name])
+            .set(status.sessions_bridge);
+        USERS.with_label_values(&[&status.name]).set(status.users);
+        GROUPS.with_label_values(&[&status.name]).set(status.groups);
+        MAC_TABLES
+            .with_label_values(&[&status.name])
+            .set(status.mac_tables);
+        IP_TABLES
+            .with_label_values(&[&status.name])
+            .set(status.ip_tables);
+        LOGINS.with_label_values(&[&status.name]).set(status.logins);
+        OUTGOING_UNICAST_PACKETS
+            .with_label_values(&[&status.name])
+            .set(status.outgoing_unicast_packets);
+        OUTGOING_UNICAST_BYTES
+            .with_label_values(&[&status.name])
+            .set(status.outgoing_unicast_bytes);
+        OUTGOING_BROADCAST_PACKETS
+            .with_label_values(&[&status.name])
+            .set(status.outgoing_broadcast_packets);
+        OUTGOING_BROADCAST_BYTES
+            .with_label_values(&[&status.name])
+            .set(status.outgoing_broadcast_bytes);
+        INCOMING_UNICAST_PACKETS
+            .with_label_values(&[&status.name])
+            .set(status.incoming_unicast_packets);
+        INCOMING_UNICAST_BYTES
+            .with_label_values(&[&status.name])
+            .set(status.incoming_unicast_bytes);
+        INCOMING_BROADCAST_PACKETS
+            .with_label_values(&[&status.name])
+            .set(status.incoming_broadcast_packets);
+        INCOMING_BROADCAST_BYTES
+            .with_label_values(&[&status.name])
+            .set(status.incoming_broadcast_bytes);
+
+        let mut protocol_sessions = HashMap::new();
+        for session in &sessions {
+            *protocol_sessions.entry(session.protocol).or_insert(0.0) += 1.0;
+        }
+        for protocol in &SessionProtocol::ALL {
+            SESSIONS_BY_PROTOCOL
+                .with_label_values(&[&status.name, protocol.as_str()])
+                .set(*protocol_sessions.get(protocol).unwrap_or(&0.0));
+        }
+
+        let mut transfer_bytes = HashMap::new();
+        let mut transfer_packets = HashMap::new();
+        for session in &sessions {
+            if let Some(val) = transfer_bytes.get(&session.user) {
+                let val = val + session.transfer_bytes;
+                transfer_bytes.insert(session.user.clone(), val);
+            } else {
+                let val = session.transfer_bytes;
+                transfer_bytes.insert(session.user.clone(), val);
+            }
+            if let Some(val) = transfer_packets.get(&session.user) {
+                let val = val + session.transfer_packets;
+                transfer_packets.insert(session.user.clone(), val);
+            } else {
+                let val = session.transfer_packets;
+                transfer_packets.insert(session.user.clone(), val);
+            }
+        }
+        for (user, bytes) in &transfer_bytes {
+            USER_TRANSFER_BYTES
+                .with_label_values(&[&status.name, user])
+                .set(*bytes);
+        }
+        for (user, packets) in &transfer_packets {
+            USER_TRANSFER_PACKETS
+                .with_label_values(&[&status.name, user])
+                .set(*packets);
+        }
+
+        // Dynamic hubs may change the type, so the previous series is removed
+        if let Some(last) = HUB_INFO_LABELS
+            .lock()
+            .unwrap()
+            .insert(status.name.clone(), status.hub_type.as_str())
+        {
+            let _ = HUB_INFO.remove_label_values(&[&status.name, last]);
+        }
+        HUB_INFO
+            .with_label_values(&[&status.name, status.hub_type.as_str()])
+            .set(1.0);
+
+        for (gauge, datetime) in &[
+            (&*LAST_LOGIN_TIMESTAMP, status.last_login),
+            (&*LAST_COMMUNICATION_TIMESTAMP, status.last_communication),
+            (&*CREATED_TIMESTAMP, status.created),
+        ] {
+            match datetime.and_then(|x| Exporter::timestamp(&x, timezone)) {
+                Some(x) => gauge.with_label_values(&[&status.name]).set(x),
+                None => {
+                    let _ = gauge.remove_label_values(&[&status.name]);
+                }
+            }
+        }
+
+        ACCESS_LISTS
+            .with_label_values(&[&status.name])
+            .set(status.access_lists);
+
+        // Hub config metrics are kept from the last collection until config_interval elapses
+        let due = HUB_CONFIG_COLLECTED
+            .lock()
+            .unwrap()
+            .get(name)
+            .is_none_or(|x| x.elapsed() >= config_interval);
+        if due {
+            match SoftEtherReader::hub_config(vpncmd, server, name, &password) {
+                Ok(config) => {
+                    HUB_CONFIG_COLLECTED
+                        .lock()
+                        .unwrap()
+                        .insert(String::from(name), Instant::now());
+                    let labels = vec![
+                        status.name.clone(),
+                        String::from(status.hub_type.as_str()),
+                        config.anonymous_enum.to_string(),
+                        config.radius.to_string(),
+                        config.security_log.to_string(),
+                        config.packet_log.to_string(),
+                    ];
+                    let mut last_labels = HUB_CONFIG_LABELS.lock().unwrap();
+                    if let Some(last) = last_labels.insert(status.name.clone(), labels.clone()) {
+                        let last: Vec<&str> = last.iter().map(|x| x.as_str()).collect();
+                        let _ = HUB_CONFIG_INFO.remove_label_values(&last);
+                    }
+                    let labels: Vec<&str> = labels.iter().map(|x| x.as_str()).collect();
+                    HUB_CONFIG_INFO.with_label_values(&labels).set(1.0);
+                    TRUSTED_CAS
                         .with_label_values(&[&status.name])
-                        .set(status.incoming_broadcast_bytes);
-
-                    let mut transfer_bytes = HashMap::new();
-                    let mut transfer_packets = HashMap::new();
-                    for session in sessions {
-                        if let Some(val) = transfer_bytes.get(&session.user) {
-                            let val = val + session.transfer_bytes;
-                            transfer_bytes.insert(session.user.clone(), val);
-                        } else {
-                            let val = session.transfer_bytes;
-                            transfer_bytes.insert(session.user.clone(), val);
-                        }
-                        if let Some(val) = transfer_packets.get(&session.user) {
-                            let val = val + session.transfer_packets;
-                            transfer_packets.insert(session.user.clone(), val);
+                        .set(config.trusted_cas);
+                    CRLS.with_label_values(&[&status.name]).set(config.crls);
+                }
+                Err(x) => {
+                    SCRAPE_ERRORS.with_label_values(&[name, x.kind()]).inc();
+                    warn!("Hub config read failed: {}", x);
+                }
+            }
+        }
+
+        Exporter::track_sessions(name, &sessions, event_log);
+        HUB_SNAPSHOTS
+            .lock()
+            .unwrap()
+            .insert(String::from(name), (status, sessions));
+        Ok(())
+    }
+
+    // Sessions are compared with the last successful collection of the hub.
+    // So logins / logouts during failed collections are detected at the next successful collection.
+    // No event is emitted at the first collection because the last sessions are unknown.
+    fn track_sessions(hub: &str, sessions: &[HubSession], event_log: Option<&Path>) {
+        let last = LAST_SESSIONS
+            .lock()
+            .unwrap()
+            .insert(String::from(hub), sessions.to_vec());
+        let events = match last {
+            Some(last) => session_event::diff(hub, &last, sessions, Local::now()),
+            None => return,
+        };
+        for event in &events {
+            let counter = match event.event {
+                EventKind::Connect => &*SESSION_CONNECTS,
+                EventKind::Disconnect => &*SESSION_DISCONNECTS,
+            };
+            counter.with_label_values(&[hub, &event.user]).inc();
+        }
+        if let Some(path) = event_log {
+            if let Err(x) = session_event::append(path, &events) {
+                warn!("Session event write failed: {}: {}", path.display(), x);
+            }
+        }
+    }
+
+    // Ready if vpncmd is found and the last collection succeeded for at least one hub.
+    // vpncmd is not run here, because probes are frequent and have short timeouts.
+    fn ready(config: &Config) -> Result<(), String> {
+        let vpncmd = config.vpncmd();
+        if SoftEtherReader::resolve_vpncmd(&vpncmd).is_none() {
+            return Err(format!("vpncmd is not found ( {} )", vpncmd));
+        }
+        if config.hubs().is_empty() {
+            return Ok(());
+        }
+
+        let states = HUB_STATES.lock().unwrap();
+        let states: Vec<_> = config
+            .hubs()
+            .iter()
+            .filter_map(|x| states.get(x.name()))
+            .collect();
+        if states.is_empty() {
+            return Err(String::from("no hub has been collected yet"));
+        }
+
+        if states.iter().any(|x| x.error.is_none()) {
+            Ok(())
+        } else {
+            Err(String::from("the last collection failed for all hubs"))
+        }
+    }
+
+    // Landing page shows the result of the last collection of each hub without running vpncmd
+    fn landing_page(config: &Config) -> String {
+        let states = HUB_STATES.lock().unwrap();
+        let snapshots = HUB_SNAPSHOTS.lock().unwrap();
+
+        let mut rows = String::new();
+        for hub in config.hubs() {
+            let name = hub.name();
+            let row = match states.get(name) {
+                Some(state) => {
+                    let timestamp = DateTime::<Local>::from(state.timestamp);
+                    let sessions = snapshots
+                        .get(name)
+                        .map(|x| x.1.len().to_string())
+                        .unwrap_or_default();
+                    format!(
+                        "<td>{}</td><td>{}</td><td>{:.3}s</td><td>{}</td><td>{}</td>",
+                        if state.error.is_none() {
+                            "ok"
                         } else {
-                            let val = session.transfer_packets;
-                            transfer_packets.insert(session.user.clone(), val);
-                        }
-                    }
-                    for (user, bytes) in &transfer_bytes {
-                        USER_TRANSFER_BYTES
-                            .with_label_values(&[&status.name, user])
-                            .set(*bytes);
-                    }
-                    for (user, packets) in &transfer_packets {
-                        USER_TRANSFER_PACKETS
-                            .with_label_values(&[&status.name, user])
-                            .set(*packets);
-                    }
+                            "failed"
+                        },
+                        timestamp.format("%Y-%m-%d %H:%M:%S %:z"),
+                        state.duration.as_secs_f64(),
+                        sessions,
+                        html_escape(state.error.as_deref().unwrap_or("")),
+                    )
                 }
+                None => String::from("<td>not collected</td><td></td><td></td><td></td><td></td>"),
+            };
+            rows.push_str(&format!("<tr><td>{}</td>{}</tr>\n", html_escape(name), row));
+        }
 
-                let git_revision = GIT_REVISION.unwrap_or("");
-                let rust_version = RUST_VERSION.unwrap_or("");
-                BUILD_INFO
-                    .with_label_values(&[&VERSION, &git_revision, &rust_version])
-                    .set(1.0);
+        format!(
+            r#"<html>
+<head><title>SoftEther Exporter</title></head>
+<body>
+<h1>SoftEther Exporter</h1>
+<p>Version: {} ( git revision: {}, rust version: {} )</p>
+<p>Server: {}</p>
+<h2>Hubs</h2>
+<table border="1">
+<tr><th>Hub</th><th>Result</th><th>Last collection</th><th>Duration</th><th>Sessions</th><th>Error</th></tr>
+{}</table>
+<h2>Endpoints</h2>
+<ul>
+<li><a href="/metrics">/metrics</a></li>
+<li><a href="/api/v1/hubs">/api/v1/hubs</a></li>
+<li><a href="/-/healthy">/-/healthy</a></li>
+<li><a href="/-/ready">/-/ready</a></li>
+</ul>
+</body>
+</html>
+"#,
+            VERSION,
+            html_escape(GIT_REVISION.unwrap_or("unknown")),
+            html_escape(RUST_VERSION.unwrap_or("unknown")),
+            html_escape(&config.server()),
+            rows
+        )
+    }
 
-                let metric_familys = prometheus::gather();
-                let mut buffer = vec![];
-                encoder.encode(&metric_familys, &mut buffer).unwrap();
-                res.headers_mut()
-                    .set(ContentType(encoder.format_type().parse::<Mime>().unwrap()));
-                res.send(&buffer).unwrap();
-            } else {
-                res.headers_mut()
-                    .set(ContentType(Mime(TopLevel::Text, SubLevel::Html, vec![])));
-                res.send(LANDING_PAGE.as_bytes()).unwrap();
+    // Snapshots of the configured hubs whose last collection succeeded
+    fn snapshots(config: &Config) -> Vec<Snapshot> {
+        let snapshots = HUB_SNAPSHOTS.lock().unwrap();
+        config
+            .hubs()
+            .iter()
+            .filter_map(|x| snapshots.get(x.name()).cloned())
+            .collect()
+    }
+
+    // JSON API serves the latest HubStatus and HubSession collected by /metrics
+    fn api(config: &Config, path: &str) -> Option<String> {
+        let snapshots = HUB_SNAPSHOTS.lock().unwrap();
+        if path == "/api/v1/hubs" {
+            let hubs: Vec<&HubStatus> = config
+                .hubs()
+                .iter()
+                .filter_map(|x| snapshots.get(x.name()))
+                .map(|x| &x.0)
+                .collect();
+            serde_json::to_string(&hubs).ok()
+        } else {
+            let hub = path
+                .strip_prefix("/api/v1/hubs/")?
+                .strip_suffix("/sessions")?;
+            if !config.hubs().iter().any(|x| x.name() == hub) {
+                return None;
             }
-        })?;
+            serde_json::to_string(&snapshots.get(hub)?.1).ok()
+        }
+    }
+
+    // The new config is swapped in only if it is valid, so the old config is kept on error
+    fn reload(config: &RwLock<Config>, file: &Path) -> Result<(), Error> {
+        let ret = Config::from_file(file).and_then(|x| {
+            x.validate()?;
+            Ok(x)
+        });
+        match ret {
+            Ok(x) => {
+                // A running collection with the old config is finished before the removal
+                let _lock = COLLECTION.blocking_lock();
+                let removed: Vec<String> = {
+                    let mut config = config.write().unwrap();
+                    let removed = config
+                        .hubs()
+                        .iter()
+                        .filter(|hub| x.hubs().iter().all(|y| y.name() != hub.name()))
+                        .map(|hub| String::from(hub.name()))
+                        .collect();
+                    *config = x;
+                    removed
+                };
+                for hub in &removed {
+                    Exporter::remove_hub(hub);
+                }
+                Exporter::reloaded(true);
+                info!("Config reloaded: {}", file.display());
+                Ok(())
+            }
+            Err(x) => {
+                Exporter::reloaded(false);
+                error!("Config reload failed: {}", x);
+                Err(x)
+            }
+        }
+    }
+
+    // Hubs removed from the config are not exported any more.
+    // Counters are removed too, so they start from 0 if the hub is added again.
+    fn remove_hub(hub: &str) {
+        for vec in &[
+            &*UP,
+            &*ONLINE,
+            &*SESSIONS,
+            &*SESSIONS_CLIENT,
+            &*SESSIONS_BRIDGE,
+            &*USERS,
+            &*GROUPS,
+            &*MAC_TABLES,
+            &*IP_TABLES,
+            &*LOGINS,
+            &*OUTGOING_UNICAST_PACKETS,
+            &*OUTGOING_UNICAST_BYTES,
+            &*OUTGOING_BROADCAST_PACKETS,
+            &*OUTGOING_BROADCAST_BYTES,
+            &*INCOMING_UNICAST_PACKETS,
+            &*INCOMING_UNICAST_BYTES,
+            &*INCOMING_BROADCAST_PACKETS,
+            &*INCOMING_BROADCAST_BYTES,
+            &*USER_TRANSFER_BYTES,
+            &*USER_TRANSFER_PACKETS,
+            &*PARSE_MISSING_FIELDS,
+            &*PARSE_UNKNOWN_FIELDS,
+            &*SESSIONS_BY_PROTOCOL,
+            &*HUB_INFO,
+            &*LAST_LOGIN_TIMESTAMP,
+            &*LAST_COMMUNICATION_TIMESTAMP,
+            &*CREATED_TIMESTAMP,
+            &*ACCESS_LISTS,
+            &*TRUSTED_CAS,
+            &*CRLS,
+            &*HUB_CONFIG_INFO,
+        ] {
+            Exporter::remove_hub_series(vec, hub);
+        }
+        for vec in &[&*SCRAPE_ERRORS, &*SESSION_CONNECTS, &*SESSION_DISCONNECTS] {
+            Exporter::remove_hub_series(vec, hub);
+        }
+        SoftEtherReader::remove_hub(hub);
+
+        HUB_INFO_LABELS.lock().unwrap().remove(hub);
+        HUB_CONFIG_LABELS.lock().unwrap().remove(hub);
+        HUB_CONFIG_COLLECTED.lock().unwrap().remove(hub);
+        HUB_SNAPSHOTS.lock().unwrap().remove(hub);
+        HUB_STATES.lock().unwrap().remove(hub);
+        LAST_SESSIONS.lock().unwrap().remove(hub);
+    }
+
+    // MetricVec removes only a series of the exact labels, so the series of the hub are listed by collect
+    fn remove_hub_series<T: MetricVecBuilder>(vec: &MetricVec<T>, hub: &str) {
+        for mf in vec.collect() {
+            for m in mf.get_metric() {
+                let labels: HashMap<&str, &str> = m
+                    .get_label()
+                    .iter()
+                    .map(|x| (x.name(), x.value()))
+                    .collect();
+                if labels.get("hub") == Some(&hub) {
+                    let _ = vec.remove(&labels);
+                }
+            }
+        }
+    }
+
+    // Reading the config file ( and password_file ) blocks, so it is run outside of the async runtime
+    async fn reload_async(config: Arc<RwLock<Config>>, file: PathBuf) -> Result<(), Error> {
+        tokio::task::spawn_blocking(move || Exporter::reload(&config, &file)).await?
+    }
+
+    fn reloaded(success: bool) {
+        CONFIG_LAST_RELOAD_SUCCESSFUL.set(if success { 1.0 } else { 0.0 });
+        if success {
+            CONFIG_LAST_RELOAD_SUCCESS_TIMESTAMP.set(Exporter::now());
+        }
+    }
 
+    #[cfg(unix)]
+    fn reload_on_sighup(config: Arc<RwLock<Config>>, file: PathBuf) -> Result<(), Error> {
+        let mut signal = signal(SignalKind::hangup())?;
+        tokio::spawn(async move {
+            while signal.recv().await.is_some() {
+                let _ = Exporter::reload_async(config.clone(), file.clone()).await;
+            }
+        });
         Ok(())
     }
+
+    // The watchdog is pinged at half of WatchdogSec, unless a collection is running longer than it.
+    // So the service is restarted by systemd if vpncmd hangs.
+    fn ping_watchdog(timeout: Duration) {
+        thread::spawn(move || loop {
+            thread::sleep(timeout / 2);
+            if Exporter::stalled(timeout) {
+                warn!("Collection is stalled: watchdog is not pinged");
+            } else {
+                systemd::notify_watchdog();
+            }
+        });
+    }
+
+    fn stalled(timeout: Duration) -> bool {
+        IN_FLIGHT
+            .lock()
+            .unwrap()
+            .values()
+            .any(|x| x.elapsed() > timeout)
+    }
+
+    // The config file is polled because editors often replace the file rather than write it in place
+    fn reload_on_change(config: Arc<RwLock<Config>>, file: PathBuf) {
+        let modified = |file: &Path| fs::metadata(file).and_then(|x| x.modified()).ok();
+        let mut last = modified(&file);
+        thread::spawn(move || loop {
+            thread::sleep(Duration::from_secs(RELOAD_POLL_INTERVAL));
+            let current = modified(&file);
+            if current.is_some() && current != last {
+                last = current;
+                let _ = Exporter::reload(&config, &file);
+            }
+        });
+    }
+
+    // vpncmd prints local time of the server, so it is converted to UTC epoch by the configured timezone.
+    // An ambiguous time at the end of DST is the earlier one, and a time skipped by the start of DST is None.
+    fn timestamp(datetime: &NaiveDateTime, timezone: &Option<Tz>) -> Option<f64> {
+        let ret = match timezone {
+            Some(tz) => tz
+                .from_local_datetime(datetime)
+                .earliest()
+                .map(|x| x.timestamp()),
+            None => Local
+                .from_local_datetime(datetime)
+                .earliest()
+                .map(|x| x.timestamp()),
+        };
+        if ret.is_none() {
+            warn!("Local time doesn't exist in the timezone: {}", datetime);
+        }
+        ret.map(|x| x as f64)
+    }
+
+    fn collect_server(vpncmd: &str, server: &str, password: &str) {
+        Exporter::collect_protocols(vpncmd, server, password);
+        Exporter::collect_ddns(vpncmd, server, password);
+    }
+
+    fn collect_protocols(vpncmd: &str, server: &str, password: &str) {
+        match SoftEtherReader::openvpn_status(vpncmd, server, password) {
+            Ok(x) => {
+                PROTOCOL_ENABLED
+                    .with_label_values(&["openvpn"])
+                    .set(if x { 1.0 } else { 0.0 })
+            }
+            Err(x) => {
+                SCRAPE_ERRORS.with_label_values(&["", x.kind()]).inc();
+                warn!("OpenVPN status read failed: {}", x);
+            }
+        }
+
+        match SoftEtherReader::sstp_status(vpncmd, server, password) {
+            Ok(x) => PROTOCOL_ENABLED
+                .with_label_values(&["sstp"])
+                .set(if x { 1.0 } else { 0.0 }),
+            Err(x) => {
+                SCRAPE_ERRORS.with_label_values(&["", x.kind()]).inc();
+                warn!("SSTP status read failed: {}", x);
+            }
+        }
+
+        match SoftEtherReader::ipsec_status(vpncmd, server, password) {
+            Ok(x) => {
+                PROTOCOL_ENABLED
+                    .with_label_values(&["l2tp_ipsec"])
+                    .set(if x.l2tp_ipsec { 1.0 } else { 0.0 });
+                PROTOCOL_ENABLED
+                    .with_label_values(&["l2tp_raw"])
+                    .set(if x.l2tp_raw { 1.0 } else { 0.0 });
+                PROTOCOL_ENABLED
+                    .with_label_values(&["etherip_ipsec"])
+                    .set(if x.etherip_ipsec { 1.0 } else { 0.0 });
+            }
+            Err(x) => {
+                SCRAPE_ERRORS.with_label_values(&["", x.kind()]).inc();
+                warn!("IPsec status read failed: {}", x);
+            }
+        }
+
+        match SoftEtherReader::etherip_clients(vpncmd, server, password) {
+            Ok(x) => {
+                let mut clients = HashMap::new();
+                for client in &x {
+                    *clients.entry(client.hub.clone()).or_insert(0.0) += 1.0;
+                }
+                ETHERIP_CLIENTS.reset();
+                for (hub, count) in &clients {
+                    ETHERIP_CLIENTS.with_label_values(&[hub]).set(*count);
+                }
+            }
+            Err(x) => {
+                SCRAPE_ERRORS.with_label_values(&["", x.kind()]).inc();
+                warn!("EtherIP client list read failed: {}", x);
+            }
+        }
+    }
+
+    fn collect_ddns(vpncmd: &str, server: &str, password: &str) {
+        match SoftEtherReader::ddns_status(vpncmd, server, password) {
+            Ok(x) => {
+                DDNS_REGISTERED.set(if x.registered() { 1.0 } else { 0.0 });
+                DDNS_INFO.reset();
+                DDNS_INFO
+                    .with_label_values(&[&x.hostname, &x.ipv4, &x.ipv6])
+                    .set(1.0);
+            }
+            Err(x) => {
+                SCRAPE_ERRORS.with_label_values(&["", x.kind()]).inc();
+                warn!("Dynamic DNS status read failed: {}", x);
+            }
+        }
+
+        match SoftEtherReader::azure_status(vpncmd, server, password) {
+            Ok(x) => {
+                AZURE_ENABLED.set(if x.enabled { 1.0 } else { 0.0 });
+                AZURE_CONNECTED.set(if x.connected { 1.0 } else { 0.0 });
+                AZURE_INFO.reset();
+                AZURE_INFO.with_label_values(&[&x.hostname]).set(1.0);
+            }
+            Err(x) => {
+                SCRAPE_ERRORS.with_label_values(&["", x.kind()]).inc();
+                warn!("VPN Azure status read failed: {}", x);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::tests::request;
+    use std::env;
+
+    fn write_config(name: &str, s: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("softether_exporter_{}.toml", name));
+        let mut file = File::create(&path).unwrap();
+        file.write_all(s.as_bytes()).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_register() {
+        // Registration errors ( e.g. duplicate registration of collectors ) panic here
+        Exporter::register();
+
+        let mfs = prometheus::gather();
+        assert!(mfs
+            .iter()
+            .any(|x| x.name() == "softether_config_last_reload_successful"));
+        #[cfg(target_os = "linux")]
+        assert_eq!(
+            mfs.iter()
+                .filter(|x| x.name() == "process_cpu_seconds_total")
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_reload() {
+        let path = write_config("reload", "[[hubs]]\nname = \"HUB1\"\n");
+        let config = RwLock::new(Config::from_file(&path).unwrap());
+
+        write_config(
+            "reload",
+            "[[hubs]]\nname = \"HUB1\"\n[[hubs]]\nname = \"HUB2\"\n",
+        );
+        assert!(Exporter::reload(&config, &path).is_ok());
+        assert_eq!(config.read().unwrap().hubs.len(), 2);
+
+        // The old config is kept if the new one is invalid
+        write_config("reload", "timezone = \"Mars/Olympus\"\n[[hubs]]\n");
+        assert!(Exporter::reload(&config, &path).is_err());
+        assert_eq!(config.read().unwrap().hubs.len(), 2);
+
+        write_config("reload", "[[hubs\n");
+        assert!(Exporter::reload(&config, &path).is_err());
+        assert_eq!(config.read().unwrap().hubs.len(), 2);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_reload_removed_hub() {
+        let path = write_config(
+            "reload_removed_hub",
+            "[[hubs]]\nname = \"REMOVE1\"\n[[hubs]]\nname = \"REMOVE2\"\n",
+        );
+        let config = RwLock::new(Config::from_file(&path).unwrap());
+        for hub in &["REMOVE1", "REMOVE2"] {
+            UP.with_label_values(&[hub]).set(1.0);
+            USER_TRANSFER_BYTES
+                .with_label_values(&[hub, "user1"])
+                .set(1.0);
+            SESSION_CONNECTS.with_label_values(&[hub, "user1"]).inc();
+            let session = HubSession::new("SID-USER1-1", "user1", 1.0, 1.0);
+            Exporter::track_sessions(hub, &[session], None);
+            HUB_STATES.lock().unwrap().insert(
+                String::from(*hub),
+                HubState {
+                    error: None,
+                    duration: Duration::from_secs(1),
+                    timestamp: SystemTime::now(),
+                },
+            );
+        }
+
+        write_config("reload_removed_hub", "[[hubs]]\nname = \"REMOVE1\"\n");
+        assert!(Exporter::reload(&config, &path).is_ok());
+        fs::remove_file(&path).unwrap();
+
+        let series = |hub: &str| {
+            prometheus::gather()
+                .iter()
+                .flat_map(|x| x.get_metric())
+                .filter(|x| x.get_label().iter().any(|x| x.value() == hub))
+                .count()
+        };
+        assert_eq!(series("REMOVE1"), 3);
+        assert_eq!(series("REMOVE2"), 0);
+        assert!(HUB_STATES.lock().unwrap().contains_key("REMOVE1"));
+        assert!(!HUB_STATES.lock().unwrap().contains_key("REMOVE2"));
+        assert!(!LAST_SESSIONS.lock().unwrap().contains_key("REMOVE2"));
+    }
+
+    #[test]
+    fn test_timestamp() {
+        let datetime = |x: &str| NaiveDateTime::parse_from_str(x, "%Y-%m-%d %H:%M:%S").unwrap();
+        let tz = |x: &str| Some(x.parse::<Tz>().unwrap());
+
+        let ret = Exporter::timestamp(&datetime("2020-04-08 09:25:49"), &tz("Asia/Tokyo"));
+        assert_eq!(ret, Some(1586305549.0));
+        let ret = Exporter::timestamp(&datetime("2020-04-08 09:25:49"), &tz("UTC"));
+        assert_eq!(ret, Some(1586337949.0));
+
+        // 01:30 occurs twice at the end of DST, and the earlier one ( EDT ) is taken
+        let ret = Exporter::timestamp(&datetime("2020-11-01 01:30:00"), &tz("America/New_York"));
+        assert_eq!(ret, Some(1604208600.0));
+
+        // 02:30 is skipped at the start of DST
+        let ret = Exporter::timestamp(&datetime("2020-03-08 02:30:00"), &tz("America/New_York"));
+        assert_eq!(ret, None);
+    }
+
+    #[test]
+    fn test_collect_once() {
+        let path = write_config("collect_once", "hubs = []\n");
+        let config = Config::from_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let output = env::temp_dir().join("softether_exporter_collect_once.prom");
+        Exporter::collect_once(config, &output).unwrap();
+
+        let s = fs::read_to_string(&output).unwrap();
+        assert!(s.contains("softether_build_info{"));
+        assert!(!s.contains("process_"));
+        fs::remove_file(&output).unwrap();
+
+        // The output is not created if the directory doesn't exist
+        let path = write_config("collect_once", "hubs = []\n");
+        let config = Config::from_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let output = env::temp_dir().join("nonexistent").join("softether.prom");
+        assert!(Exporter::collect_once(config, &output).is_err());
+    }
+
+    #[test]
+    fn test_routing() {
+        let path = write_config("routing", "hubs = []\n");
+        let config = Config::from_file(&path).unwrap();
+        let state = Arc::new(State {
+            config: Arc::new(RwLock::new(config)),
+            config_file: path.clone(),
+            web_config: WebConfig::default(),
+            encoder: TextEncoder::new(),
+            openmetrics_encoder: OpenMetricsEncoder::new(Exporter::now()),
+        });
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let listener = runtime.block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handler = move |req| Exporter::handle(state.clone(), req);
+        runtime.spawn(server::serve(
+            listener,
+            None,
+            Duration::from_secs(10),
+            handler,
+            std::future::pending(),
+        ));
+
+        let ret = request(addr, "GET", "/metrics");
+        assert!(ret.starts_with("HTTP/1.0 200 OK"));
+        assert!(ret.contains("softether_build_info{"));
+        let ret = request(addr, "GET", "/");
+        assert!(ret.starts_with("HTTP/1.0 200 OK"));
+        assert!(ret.contains("<a href=\"/metrics\">"));
+        let ret = request(addr, "POST", "/-/reload");
+        assert!(ret.starts_with("HTTP/1.0 200 OK"));
+        let ret = request(addr, "GET", "/-/reload");
+        assert!(ret.starts_with("HTTP/1.0 405 Method Not Allowed"));
+        let ret = request(addr, "GET", "/-/healthy");
+        assert!(ret.starts_with("HTTP/1.0 200 OK"));
+        let ret = request(addr, "POST", "/-/ready");
+        assert!(ret.starts_with("HTTP/1.0 405 Method Not Allowed"));
+        let ret = request(addr, "GET", "/unknown");
+        assert!(ret.starts_with("HTTP/1.0 404 Not Found"));
+        let ret = request(addr, "GET", "/api/v1/hubs/unknown/sessions");
+        assert!(ret.starts_with("HTTP/1.0 404 Not Found"));
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_ready() {
+        let path = write_config(
+            "ready",
+            "vpncmd = \"/nonexistent/vpncmd\"\n[[hubs]]\nname = \"READY1\"\n[[hubs]]\nname = \"READY2\"\n",
+        );
+        let mut config = Config::from_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(Exporter::ready(&config)
+            .unwrap_err()
+            .starts_with("vpncmd is not found"));
+
+        // Any executable in PATH is enough for the check of vpncmd
+        config.vpncmd = Some(String::from("sh"));
+        assert_eq!(
+            Exporter::ready(&config).unwrap_err(),
+            "no hub has been collected yet"
+        );
+        let state = |error: Option<&str>| HubState {
+            error: error.map(String::from),
+            duration: Duration::from_secs(1),
+            timestamp: SystemTime::now(),
+        };
+        {
+            let mut states = HUB_STATES.lock().unwrap();
+            states.insert(String::from("READY1"), state(Some("failed")));
+            states.insert(String::from("READY2"), state(Some("failed")));
+        }
+        assert!(Exporter::ready(&config).is_err());
+        HUB_STATES
+            .lock()
+            .unwrap()
+            .insert(String::from("READY2"), state(None));
+        assert!(Exporter::ready(&config).is_ok());
+
+        config.hubs.clear();
+        assert!(Exporter::ready(&config).is_ok());
+    }
+
+    #[test]
+    fn test_stalled() {
+        let in_flight = InFlight::new();
+        let id = in_flight.0;
+        thread::sleep(Duration::from_millis(10));
+        assert!(Exporter::stalled(Duration::from_millis(1)));
+        assert!(!Exporter::stalled(Duration::from_secs(3600)));
+        drop(in_flight);
+        assert!(!IN_FLIGHT.lock().unwrap().contains_key(&id));
+    }
+
+    #[test]
+    fn test_landing_page() {
+        let path = write_config(
+            "landing_page",
+            "server = \"vpn.example.com\"\n[[hubs]]\nname = \"LAND1\"\n[[hubs]]\nname = \"LAND2\"\n[[hubs]]\nname = \"LAND3\"\n",
+        );
+        let config = Config::from_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let mut status = HubStatus::new();
+        status.name = String::from("LAND1");
+        let session = HubSession::new("SID-USER1-1", "user1", 0.0, 0.0);
+        HUB_SNAPSHOTS
+            .lock()
+            .unwrap()
+            .insert(String::from("LAND1"), (status, vec![session]));
+        {
+            let mut states = HUB_STATES.lock().unwrap();
+            states.insert(
+                String::from("LAND1"),
+                HubState {
+                    error: None,
+                    duration: Duration::from_millis(1500),
+                    timestamp: SystemTime::now(),
+                },
+            );
+            states.insert(
+                String::from("LAND2"),
+                HubState {
+                    error: Some(String::from("Hub status read failed: <error>")),
+                    duration: Duration::from_millis(250),
+                    timestamp: SystemTime::now(),
+                },
+            );
+        }
+
+        let page = Exporter::landing_page(&config);
+        assert!(page.contains(&format!("<p>Version: {} ", VERSION)));
+        assert!(page.contains("<p>Server: vpn.example.com</p>"));
+        assert!(page.contains("<tr><td>LAND1</td><td>ok</td>"));
+        assert!(page.contains("<td>1.500s</td><td>1</td><td></td></tr>"));
+        assert!(page.contains("<tr><td>LAND2</td><td>failed</td>"));
+        assert!(page.contains(
+            "<td>0.250s</td><td></td><td>Hub status read failed: &lt;error&gt;</td></tr>"
+        ));
+        assert!(page.contains("<tr><td>LAND3</td><td>not collected</td>"));
+        assert!(page.contains("<a href=\"/metrics\">"));
+    }
+
+    #[test]
+    fn test_api() {
+        let path = write_config(
+            "api",
+            "[[hubs]]\nname = \"API1\"\n[[hubs]]\nname = \"API2\"\n",
+        );
+        let config = Config::from_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let mut status = HubStatus::new();
+        status.name = String::from("API1");
+        status.secure_nat = true;
+        let mut session = HubSession::new("SID-USER1-[OPENVPN_L3]-1", "user1", 100.0, 10.0);
+        session.vlan_id = Some(10);
+        HUB_SNAPSHOTS
+            .lock()
+            .unwrap()
+            .insert(String::from("API1"), (status, vec![session]));
+
+        let hubs = Exporter::api(&config, "/api/v1/hubs").unwrap();
+        assert!(hubs.starts_with("[{\"name\":\"API1\""));
+        assert!(hubs.contains("\"secure_nat\":true"));
+        assert!(hubs.contains("\"hub_type\":\"unknown\""));
+        assert!(!hubs.contains("report"));
+
+        let sessions = Exporter::api(&config, "/api/v1/hubs/API1/sessions").unwrap();
+        assert!(sessions.contains("\"protocol\":\"openvpn\""));
+        assert!(sessions.contains("\"vlan_id\":10"));
+        assert!(sessions.contains("\"location\":\"Local Session\""));
+
+        // API2 is not collected yet, and API3 is not configured
+        assert!(Exporter::api(&config, "/api/v1/hubs/API2/sessions").is_none());
+        assert!(Exporter::api(&config, "/api/v1/hubs/API3/sessions").is_none());
+        assert!(Exporter::api(&config, "/api/v1/hubs/API1").is_none());
+    }
+
+    #[test]
+    fn test_track_sessions() {
+        let path = env::temp_dir().join("softether_exporter_track_sessions.jsonl");
+        let _ = fs::remove_file(&path);
+
+        let session = |name: &str, user: &str| HubSession::new(name, user, 100.0, 10.0);
+
+        // No event at the first collection
+        Exporter::track_sessions("TRACK1", &[session("SID-1", "user1")], Some(&path));
+        assert!(!path.exists());
+
+        let last = vec![session("SID-1", "user1"), session("SID-2", "user2")];
+        LAST_SESSIONS
+            .lock()
+            .unwrap()
+            .insert(String::from("TRACK1"), last);
+        let current = vec![
+            session("SID-2", "user2"),
+            session("SID-3", "user1"),
+            session("SID-4", "user1"),
+        ];
+        Exporter::track_sessions("TRACK1", &current, Some(&path));
+
+        let connects = |user: &str| SESSION_CONNECTS.with_label_values(&["TRACK1", user]).get();
+        let disconnects = |user: &str| {
+            SESSION_DISCONNECTS
+                .with_label_values(&["TRACK1", user])
+                .get()
+        };
+        assert_eq!(connects("user1"), 2);
+        assert_eq!(disconnects("user1"), 1);
+        assert_eq!(connects("user2"), 0);
+        assert_eq!(disconnects("user2"), 0);
+
+        let log = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(
+            lines[0].contains("\"event\":\"disconnect\",\"hub\":\"TRACK1\",\"session\":\"SID-1\"")
+        );
+        assert!(lines[0].contains("\"transfer_bytes\":100.0"));
+        assert!(lines[1].contains("\"event\":\"connect\",\"hub\":\"TRACK1\",\"session\":\"SID-3\""));
+        assert!(lines[2].contains("\"session\":\"SID-4\""));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_track_sessions_after_failure() {
+        let path = write_config(
+            "track_sessions_after_failure",
+            "vpncmd = \"/nonexistent/vpncmd\"\n[[hubs]]\nname = \"TRACK2\"\n",
+        );
+        let config = Config::from_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let mut session = HubSession::new("SID-1", "user1", 100.0, 10.0);
+        Exporter::track_sessions("TRACK2", &[session.clone()], None);
+
+        // The collection fails, and SID-1 is closed and SID-2 is opened during the outage
+        Exporter::collect(&config);
+        assert!(HUB_STATES.lock().unwrap()["TRACK2"].error.is_some());
+        session.name = String::from("SID-2");
+        Exporter::track_sessions("TRACK2", &[session], None);
+
+        let connects = SESSION_CONNECTS
+            .with_label_values(&["TRACK2", "user1"])
+            .get();
+        let disconnects = SESSION_DISCONNECTS
+            .with_label_values(&["TRACK2", "user1"])
+            .get();
+        assert_eq!((connects, disconnects), (1, 1));
+    }
+
+    // Fake vpncmd which logs the commands and prints testdata/en, with the hub name in StatusGet.
+    // SessionList is printed from SessionList.csv in the directory.
+    #[cfg(unix)]
+    fn fake_vpncmd(dir: &Path, hub: &str, delay: f64) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let vpncmd = dir.join("vpncmd");
+        let testdata = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/en");
+        let script = format!(
+            "#!/bin/sh\ncat > /dev/null\nfor cmd; do :; done\necho \"$cmd\" >> {log:?}\n\
+             case \"$cmd\" in\n\
+             StatusGet) sleep {delay}; sed s/DEFAULT/{hub}/ {testdata:?}/StatusGet.csv ;;\n\
+             SessionList) cat {sessions:?} ;;\n\
+             *) cat {testdata:?}/$cmd.csv ;;\nesac\n",
+            log = dir.join("vpncmd.log"),
+            delay = delay,
+            hub = hub,
+            testdata = testdata,
+            sessions = dir.join("SessionList.csv"),
+        );
+        fs::write(&vpncmd, script).unwrap();
+        fs::set_permissions(&vpncmd, fs::Permissions::from_mode(0o755)).unwrap();
+        fs::write(
+            dir.join("SessionList.csv"),
+            "Session Name,VLAN ID,Location,User Name,Source Host Name,TCP Connections,Transfer Bytes,Transfer Packets\n",
+        )
+        .unwrap();
+        vpncmd
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_overlapping_collections() {
+        use std::sync::Barrier;
+
+        // vpncmd is slow enough for the collections to overlap
+        let dir = env::temp_dir().join("softether_exporter_overlapping_collections");
+        let vpncmd = fake_vpncmd(&dir, "OVERLAP", 0.5);
+        let sessions = |name: &str| {
+            let csv = format!(
+                "Session Name,VLAN ID,Location,User Name,Source Host Name,TCP Connections,Transfer Bytes,Transfer Packets\n\
+                 {},-,Local Session,user1,192.0.2.1,1 / 1,100,10\n",
+                name
+            );
+            fs::write(dir.join("SessionList.csv"), csv).unwrap();
+        };
+
+        let path = write_config(
+            "overlapping_collections",
+            &format!("vpncmd = {:?}\n[[hubs]]\nname = \"OVERLAP\"\n", vpncmd),
+        );
+        let config = Config::from_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        sessions("SID-USER1-1");
+        Exporter::collect(&config);
+        sessions("SID-USER1-2");
+
+        // The later collection shares the result of the running one
+        let barrier = Arc::new(Barrier::new(2));
+        let handles: Vec<_> = (0..2)
+            .map(|_| {
+                let config = config.clone();
+                let barrier = barrier.clone();
+                thread::spawn(move || {
+                    barrier.wait();
+                    Exporter::collect(&config);
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let log = fs::read_to_string(dir.join("vpncmd.log")).unwrap();
+        assert_eq!(log.lines().filter(|x| *x == "SessionList").count(), 2);
+        assert!(HUB_STATES.lock().unwrap()["OVERLAP"].error.is_none());
+        assert_eq!(
+            LAST_SESSIONS.lock().unwrap()["OVERLAP"][0].name,
+            "SID-USER1-2"
+        );
+        let connects = SESSION_CONNECTS
+            .with_label_values(&["OVERLAP", "user1"])
+            .get();
+        let disconnects = SESSION_DISCONNECTS
+            .with_label_values(&["OVERLAP", "user1"])
+            .get();
+        assert_eq!((connects, disconnects), (1, 1));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_config_interval() {
+        let dir = env::temp_dir().join("softether_exporter_config_interval");
+        let vpncmd = fake_vpncmd(&dir, "CONFIG1", 0.0);
+        let path = write_config(
+            "config_interval",
+            &format!("vpncmd = {:?}\n[[hubs]]\nname = \"CONFIG1\"\n", vpncmd),
+        );
+        let mut config = Config::from_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let count = |cmd: &str| {
+            let log = fs::read_to_string(dir.join("vpncmd.log")).unwrap();
+            log.lines().filter(|x| *x == cmd).count()
+        };
+
+        // Hub config is collected at the first collection, and kept until config_interval elapses
+        Exporter::collect(&config);
+        Exporter::collect(&config);
+        assert_eq!((count("StatusGet"), count("OptionsGet")), (2, 1));
+        assert_eq!(TRUSTED_CAS.with_label_values(&["CONFIG1"]).get(), 2.0);
+
+        config.config_interval = Some(0);
+        Exporter::collect(&config);
+        assert_eq!((count("StatusGet"), count("OptionsGet")), (3, 2));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,118 @@
+use anyhow::{anyhow, Error};
+use chrono::{DateTime, Local, SecondsFormat};
+use log::{Level, LevelFilter};
+use std::io::Write;
+use std::str::FromStr;
+
+// -------------------------------------------------------------------------------------------------
+// LogFormat
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            x => Err(anyhow!("unknown log format: {}", x)),
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Logger
+// -------------------------------------------------------------------------------------------------
+
+// Log messages are written to stderr, so that stdout is kept for the output of subcommands
+pub fn init(level: LevelFilter, format: LogFormat) {
+    env_logger::Builder::new()
+        .filter_level(level)
+        .format(move |buf, record| {
+            let line = format_record(
+                format,
+                Local::now(),
+                record.level(),
+                record.target(),
+                &record.args().to_string(),
+            );
+            writeln!(buf, "{}", line)
+        })
+        .init();
+}
+
+fn format_record(
+    format: LogFormat,
+    time: DateTime<Local>,
+    level: Level,
+    target: &str,
+    msg: &str,
+) -> String {
+    let time = time.to_rfc3339_opts(SecondsFormat::Millis, false);
+    match format {
+        LogFormat::Text => format!("{} {:<5} {}", time, level, msg),
+        LogFormat::Json => serde_json::json!({
+            "time": time,
+            "level": level.as_str().to_lowercase(),
+            "target": target,
+            "msg": msg,
+        })
+        .to_string(),
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Test
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_format_record() {
+        let time = Local.timestamp_opt(1600000000, 123_000_000).unwrap();
+        let ts = time.to_rfc3339_opts(SecondsFormat::Millis, false);
+
+        let ret = format_record(
+            LogFormat::Text,
+            time,
+            Level::Info,
+            "softether_exporter::exporter",
+            "Server started: http://0.0.0.0:9411",
+        );
+        assert_eq!(
+            ret,
+            format!("{} INFO  Server started: http://0.0.0.0:9411", ts)
+        );
+
+        let ret = format_record(
+            LogFormat::Json,
+            time,
+            Level::Warn,
+            "softether_exporter::exporter",
+            "Hub status read failed: \"HUB1\"",
+        );
+        assert_eq!(
+            ret,
+            format!(
+                r#"{{"level":"warn","msg":"Hub status read failed: \"HUB1\"","target":"softether_exporter::exporter","time":"{}"}}"#,
+                ts
+            )
+        );
+    }
+
+    #[test]
+    fn test_log_format() {
+        assert_eq!(LogFormat::from_str("text").unwrap(), LogFormat::Text);
+        assert_eq!(LogFormat::from_str("json").unwrap(), LogFormat::Json);
+        assert!(LogFormat::from_str("logfmt").is_err());
+    }
+}
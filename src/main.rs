@@ -1,10 +1,26 @@
+mod check;
 mod exporter;
+mod logger;
+mod openmetrics;
+mod push;
+mod server;
+mod session_event;
+mod sink;
 mod softether_reader;
+mod strtable;
+mod systemd;
+mod web_config;
 
+use crate::check::Check;
 use crate::exporter::{Config, Exporter};
+use crate::logger::LogFormat;
+use crate::web_config::WebConfig;
 use anyhow::Error;
+use log::{error, LevelFilter};
 use std::env;
+use std::io;
 use std::path::PathBuf;
+use std::time::Duration;
 use structopt::{clap, StructOpt};
 
 // -------------------------------------------------------------------------------------------------
@@ -24,9 +40,47 @@ pub struct Opt {
     #[structopt(long = "config.file")]
     pub config: PathBuf,
 
-    /// Show verbose message
+    /// Web config file for TLS and basic authentication.
+    #[structopt(long = "web.config.file")]
+    pub web_config: Option<PathBuf>,
+
+    /// Timeout of each request in seconds.
+    #[structopt(long = "web.request-timeout", default_value = "60")]
+    pub request_timeout: u64,
+
+    /// Log level ( error, warn, info, debug or trace ).
+    #[structopt(long = "log.level", default_value = "info")]
+    pub log_level: LevelFilter,
+
+    /// Log format ( text or json ).
+    #[structopt(long = "log.format", default_value = "text")]
+    pub log_format: LogFormat,
+
+    /// Show verbose message ( same as --log.level=debug )
     #[structopt(short = "v", long = "verbose")]
     pub verbose: bool,
+
+    #[structopt(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum Command {
+    /// Check the config file and the connection to each hub, and exit.
+    #[structopt(name = "check")]
+    Check,
+
+    /// Collect metrics once and write them to a file for the node_exporter textfile collector.
+    #[structopt(name = "collect")]
+    Collect {
+        /// Output file ( e.g. /var/lib/node_exporter/textfile_collector/softether.prom ).
+        #[structopt(long = "output")]
+        output: PathBuf,
+    },
+
+    /// Collect metrics periodically and push them to Pushgateway, remote_write, InfluxDB, Graphite or StatsD.
+    #[structopt(name = "push")]
+    Push,
 }
 
 // -------------------------------------------------------------------------------------------------
@@ -36,14 +90,52 @@ pub struct Opt {
 fn run() -> Result<(), Error> {
     let opt = Opt::from_args();
 
+    let log_level = if opt.verbose {
+        LevelFilter::Debug.max(opt.log_level)
+    } else {
+        opt.log_level
+    };
+    logger::init(log_level, opt.log_format);
+
+    if let Some(Command::Check) = opt.command {
+        let check = Check::run(&opt.config);
+        check.print(&mut io::stdout())?;
+        if !check.passed() {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     let config = Config::from_file(&opt.config)?;
 
-    Exporter::start(config, &opt.listen_address, opt.verbose)?;
+    if let Some(Command::Collect { ref output }) = opt.command {
+        Exporter::collect_once(config, output)?;
+        return Ok(());
+    }
+
+    if let Some(Command::Push) = opt.command {
+        Exporter::push(config)?;
+        return Ok(());
+    }
+
+    let web_config = match opt.web_config {
+        Some(ref x) => WebConfig::from_file(x)?,
+        None => WebConfig::default(),
+    };
+
+    Exporter::start(
+        config,
+        &opt.config,
+        &opt.listen_address,
+        web_config,
+        Duration::from_secs(opt.request_timeout),
+    )?;
     Ok(())
 }
 
 fn main() {
     if let Err(x) = run() {
-        println!("{}", x);
+        error!("{}", x);
+        std::process::exit(1);
     }
 }
@@ -0,0 +1,299 @@
+use prometheus::proto::{LabelPair, MetricFamily, MetricType};
+use std::io::{self, Write};
+
+pub static OPENMETRICS_FORMAT: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+// -------------------------------------------------------------------------------------------------
+// Content negotiation
+// -------------------------------------------------------------------------------------------------
+
+// OpenMetrics is chosen if its quality in Accept header is not lower than the text format
+pub fn accepts_openmetrics(accept: &str) -> bool {
+    let mut openmetrics: f64 = 0.0;
+    let mut text: f64 = 0.0;
+    for range in accept.split(',') {
+        let mut params = range.split(';').map(|x| x.trim());
+        let media = params.next().unwrap_or("");
+        let q = params
+            .filter_map(|x| x.strip_prefix("q="))
+            .next()
+            .and_then(|x| x.parse::<f64>().ok())
+            .unwrap_or(1.0);
+        match media {
+            "application/openmetrics-text" => openmetrics = openmetrics.max(q),
+            "text/plain" | "text/*" | "*/*" => text = text.max(q),
+            _ => (),
+        }
+    }
+    openmetrics > 0.0 && openmetrics >= text
+}
+
+// -------------------------------------------------------------------------------------------------
+// OpenMetricsEncoder
+// -------------------------------------------------------------------------------------------------
+
+// prometheus crate doesn't support OpenMetrics 1.0, so MetricFamily is encoded here.
+// The crate doesn't track the creation time of each series either, so `_created` is the start time
+// of the exporter, which all counters and histograms are counted from.
+// Series which appear later ( e.g. a new user ) get the same time, which is earlier than the real one.
+pub struct OpenMetricsEncoder {
+    created: f64,
+}
+
+impl OpenMetricsEncoder {
+    pub fn new(created: f64) -> OpenMetricsEncoder {
+        OpenMetricsEncoder { created }
+    }
+
+    pub fn encode<W: Write>(
+        &self,
+        metric_families: &[MetricFamily],
+        w: &mut W,
+    ) -> Result<(), io::Error> {
+        for mf in metric_families {
+            let metric_type = mf.get_field_type();
+            // The family name of counters doesn't have `_total` suffix
+            let name = if metric_type == MetricType::COUNTER {
+                mf.name().strip_suffix("_total").unwrap_or(mf.name())
+            } else {
+                mf.name()
+            };
+
+            if !mf.help().is_empty() {
+                writeln!(w, "# HELP {} {}", name, escape(mf.help()))?;
+            }
+            let type_str = match metric_type {
+                MetricType::COUNTER => "counter",
+                MetricType::GAUGE => "gauge",
+                MetricType::HISTOGRAM => "histogram",
+                MetricType::SUMMARY => "summary",
+                MetricType::UNTYPED => "unknown",
+            };
+            writeln!(w, "# TYPE {} {}", name, type_str)?;
+            if let Some(unit) = unit(name) {
+                writeln!(w, "# UNIT {} {}", name, unit)?;
+            }
+
+            for m in mf.get_metric() {
+                let labels = m.get_label();
+                match metric_type {
+                    MetricType::COUNTER => {
+                        let value = m.get_counter().value();
+                        sample(w, name, "_total", labels, None, value)?;
+                        sample(w, name, "_created", labels, None, self.created)?;
+                    }
+                    MetricType::GAUGE => {
+                        sample(w, name, "", labels, None, m.get_gauge().value())?;
+                    }
+                    MetricType::HISTOGRAM => {
+                        let h = m.get_histogram();
+                        let mut inf_seen = false;
+                        for b in h.get_bucket() {
+                            let le = float(b.upper_bound());
+                            let count = b.cumulative_count() as f64;
+                            sample(w, name, "_bucket", labels, Some(("le", &le)), count)?;
+                            inf_seen |= b.upper_bound() == f64::INFINITY;
+                        }
+                        let count = h.get_sample_count() as f64;
+                        if !inf_seen {
+                            sample(w, name, "_bucket", labels, Some(("le", "+Inf")), count)?;
+                        }
+                        sample(w, name, "_count", labels, None, count)?;
+                        sample(w, name, "_sum", labels, None, h.get_sample_sum())?;
+                        sample(w, name, "_created", labels, None, self.created)?;
+                    }
+                    MetricType::SUMMARY => {
+                        let s = m.get_summary();
+                        for q in s.get_quantile() {
+                            let quantile = float(q.quantile());
+                            sample(
+                                w,
+                                name,
+                                "",
+                                labels,
+                                Some(("quantile", &quantile)),
+                                q.value(),
+                            )?;
+                        }
+                        sample(w, name, "_count", labels, None, s.sample_count() as f64)?;
+                        sample(w, name, "_sum", labels, None, s.sample_sum())?;
+                        sample(w, name, "_created", labels, None, self.created)?;
+                    }
+                    MetricType::UNTYPED => {
+                        sample(w, name, "", labels, None, m.untyped.value())?;
+                    }
+                }
+            }
+        }
+        writeln!(w, "# EOF")
+    }
+}
+
+fn sample<W: Write>(
+    w: &mut W,
+    name: &str,
+    suffix: &str,
+    labels: &[LabelPair],
+    additional_label: Option<(&str, &str)>,
+    value: f64,
+) -> Result<(), io::Error> {
+    let mut pairs: Vec<String> = labels
+        .iter()
+        .map(|x| format!("{}=\"{}\"", x.name(), escape(x.value())))
+        .collect();
+    if let Some((label, value)) = additional_label {
+        pairs.push(format!("{}=\"{}\"", label, value));
+    }
+    if pairs.is_empty() {
+        writeln!(w, "{}{} {}", name, suffix, float(value))
+    } else {
+        writeln!(
+            w,
+            "{}{}{{{}}} {}",
+            name,
+            suffix,
+            pairs.join(","),
+            float(value)
+        )
+    }
+}
+
+// The unit is declared only if the family name has the suffix of the unit
+fn unit(name: &str) -> Option<&'static str> {
+    if name.ends_with("_bytes") {
+        Some("bytes")
+    } else if name.ends_with("_seconds") {
+        Some("seconds")
+    } else {
+        None
+    }
+}
+
+fn float(value: f64) -> String {
+    if value.is_nan() {
+        String::from("NaN")
+    } else if value.is_infinite() {
+        String::from(if value > 0.0 { "+Inf" } else { "-Inf" })
+    } else if value.fract() == 0.0 && value.abs() < 1e15 {
+        format!("{:.1}", value)
+    } else {
+        value.to_string()
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+// -------------------------------------------------------------------------------------------------
+// Test
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::{
+        CounterVec, Encoder, GaugeVec, Histogram, HistogramOpts, Opts, Registry, TextEncoder,
+    };
+
+    fn snapshot() -> Vec<MetricFamily> {
+        let registry = Registry::new();
+        let bytes = GaugeVec::new(
+            Opts::new("softether_user_transfer_bytes", "User transfer in bytes"),
+            &["hub", "user"],
+        )
+        .unwrap();
+        let errors = CounterVec::new(
+            Opts::new("softether_scrape_errors_total", "Number of vpncmd errors"),
+            &["hub", "kind"],
+        )
+        .unwrap();
+        let duration = Histogram::with_opts(
+            HistogramOpts::new(
+                "softether_scrape_duration_seconds",
+                "Duration of vpncmd execution",
+            )
+            .buckets(vec![0.5, 1.0]),
+        )
+        .unwrap();
+        registry.register(Box::new(bytes.clone())).unwrap();
+        registry.register(Box::new(errors.clone())).unwrap();
+        registry.register(Box::new(duration.clone())).unwrap();
+
+        bytes.with_label_values(&["HUB1", "user\"1"]).set(1024.0);
+        errors
+            .with_label_values(&["HUB1", "auth_failed"])
+            .inc_by(2.0);
+        duration.observe(0.25);
+        duration.observe(2.0);
+        registry.gather()
+    }
+
+    #[test]
+    fn test_openmetrics() {
+        let encoder = OpenMetricsEncoder::new(1600000000.0);
+        let mut buf = Vec::new();
+        encoder.encode(&snapshot(), &mut buf).unwrap();
+
+        let expected = r#"# HELP softether_scrape_duration_seconds Duration of vpncmd execution
+# TYPE softether_scrape_duration_seconds histogram
+# UNIT softether_scrape_duration_seconds seconds
+softether_scrape_duration_seconds_bucket{le="0.5"} 1.0
+softether_scrape_duration_seconds_bucket{le="1.0"} 1.0
+softether_scrape_duration_seconds_bucket{le="+Inf"} 2.0
+softether_scrape_duration_seconds_count 2.0
+softether_scrape_duration_seconds_sum 2.25
+softether_scrape_duration_seconds_created 1600000000.0
+# HELP softether_scrape_errors Number of vpncmd errors
+# TYPE softether_scrape_errors counter
+softether_scrape_errors_total{hub="HUB1",kind="auth_failed"} 2.0
+softether_scrape_errors_created{hub="HUB1",kind="auth_failed"} 1600000000.0
+# HELP softether_user_transfer_bytes User transfer in bytes
+# TYPE softether_user_transfer_bytes gauge
+# UNIT softether_user_transfer_bytes bytes
+softether_user_transfer_bytes{hub="HUB1",user="user\"1"} 1024.0
+# EOF
+"#;
+        assert_eq!(String::from_utf8(buf).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_text() {
+        let encoder = TextEncoder::new();
+        let mut buf = Vec::new();
+        encoder.encode(&snapshot(), &mut buf).unwrap();
+
+        let expected = r#"# HELP softether_scrape_duration_seconds Duration of vpncmd execution
+# TYPE softether_scrape_duration_seconds histogram
+softether_scrape_duration_seconds_bucket{le="0.5"} 1
+softether_scrape_duration_seconds_bucket{le="1"} 1
+softether_scrape_duration_seconds_bucket{le="+Inf"} 2
+softether_scrape_duration_seconds_sum 2.25
+softether_scrape_duration_seconds_count 2
+# HELP softether_scrape_errors_total Number of vpncmd errors
+# TYPE softether_scrape_errors_total counter
+softether_scrape_errors_total{hub="HUB1",kind="auth_failed"} 2
+# HELP softether_user_transfer_bytes User transfer in bytes
+# TYPE softether_user_transfer_bytes gauge
+softether_user_transfer_bytes{hub="HUB1",user="user\"1"} 1024
+"#;
+        assert_eq!(String::from_utf8(buf).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_accepts_openmetrics() {
+        // Accept header sent by Prometheus 2.x
+        assert!(accepts_openmetrics(
+            "application/openmetrics-text;version=1.0.0;q=0.5,application/openmetrics-text;version=0.0.1;q=0.4,text/plain;version=0.0.4;q=0.3,*/*;q=0.2"
+        ));
+        assert!(accepts_openmetrics("application/openmetrics-text"));
+        assert!(!accepts_openmetrics(
+            "text/plain;version=0.0.4;q=1.0,application/openmetrics-text;q=0.5"
+        ));
+        assert!(!accepts_openmetrics("text/plain"));
+        assert!(!accepts_openmetrics("*/*"));
+        assert!(!accepts_openmetrics(""));
+    }
+}
@@ -0,0 +1,1043 @@
+use crate::sink::{self, Snapshot, StatsdState};
+use anyhow::{anyhow, Error};
+use base64::engine::general_purpose::URL_SAFE;
+use base64::Engine;
+use log::{info, warn};
+use prometheus::proto::{MetricFamily, MetricType};
+use prometheus::{Encoder, TextEncoder};
+use prost::Message;
+use serde::Deserialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, Write};
+use std::net::{TcpStream, ToSocketAddrs, UdpSocket};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// -------------------------------------------------------------------------------------------------
+// PushConfig
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct PushConfig {
+    interval: Option<u64>,
+    job: Option<String>,
+    pushgateway: Option<String>,
+    remote_write: Option<String>,
+    influxdb: Option<String>,
+    influxdb_token: Option<String>,
+    graphite: Option<String>,
+    graphite_prefix: Option<String>,
+    statsd: Option<String>,
+    statsd_prefix: Option<String>,
+    statsd_format: Option<StatsdFormat>,
+    timeout: Option<u64>,
+    retries: Option<u32>,
+    buffer: Option<usize>,
+}
+
+impl PushConfig {
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.pushgateway.is_none()
+            && self.remote_write.is_none()
+            && self.influxdb.is_none()
+            && self.graphite.is_none()
+            && self.statsd.is_none()
+        {
+            return Err(anyhow!(
+                "any of pushgateway, remote_write, influxdb, graphite or statsd is required in [push]"
+            ));
+        }
+        let influxdb = self.influxdb.iter().filter(|x| x.as_str() != "stdout");
+        for url in self
+            .pushgateway
+            .iter()
+            .chain(self.remote_write.iter())
+            .chain(influxdb)
+        {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                return Err(anyhow!("push URL must be http:// or https://: {}", url));
+            }
+        }
+        if self.interval() == Duration::from_secs(0) {
+            return Err(anyhow!("push interval must be positive"));
+        }
+        Ok(())
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval.unwrap_or(60))
+    }
+
+    fn job(&self) -> String {
+        self.job.clone().unwrap_or(String::from("softether"))
+    }
+
+    fn graphite_prefix(&self) -> String {
+        self.graphite_prefix
+            .clone()
+            .unwrap_or(String::from("softether"))
+    }
+
+    fn statsd_prefix(&self) -> String {
+        self.statsd_prefix
+            .clone()
+            .unwrap_or(String::from("softether"))
+    }
+
+    fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout.unwrap_or(10))
+    }
+
+    fn retries(&self) -> u32 {
+        self.retries.unwrap_or(3)
+    }
+
+    fn buffer(&self) -> usize {
+        self.buffer.unwrap_or(100)
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StatsdFormat {
+    Statsd,
+    Dogstatsd,
+}
+
+// Max size of a StatsD packet, which is not fragmented in most networks
+static STATSD_PACKET_SIZE: usize = 1432;
+
+// -------------------------------------------------------------------------------------------------
+// remote_write protocol
+// -------------------------------------------------------------------------------------------------
+
+// prometheus/prompb/remote.proto and types.proto ( only the fields used by remote_write 1.0 )
+#[derive(Clone, PartialEq, Message)]
+pub struct WriteRequest {
+    #[prost(message, repeated, tag = "1")]
+    pub timeseries: Vec<TimeSeries>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct TimeSeries {
+    #[prost(message, repeated, tag = "1")]
+    pub labels: Vec<Label>,
+    #[prost(message, repeated, tag = "2")]
+    pub samples: Vec<Sample>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Label {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(string, tag = "2")]
+    pub value: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Sample {
+    #[prost(double, tag = "1")]
+    pub value: f64,
+    #[prost(int64, tag = "2")]
+    pub timestamp: i64,
+}
+
+// -------------------------------------------------------------------------------------------------
+// Pusher
+// -------------------------------------------------------------------------------------------------
+
+enum PushError {
+    // Network errors, 5xx and 429 are retried
+    Retryable(String),
+    // Other 4xx means the data is rejected, so it is never accepted by retry
+    Rejected(String),
+}
+
+pub struct Pusher {
+    config: PushConfig,
+    server: String,
+    agent: ureq::Agent,
+    backoff: Duration,
+    // Retries of each sink are stopped at the deadline of the current push
+    deadline: Instant,
+    // WriteRequests which are not sent by outage of the remote_write endpoint
+    buffer: Mutex<VecDeque<WriteRequest>>,
+    statsd: StatsdState,
+}
+
+impl Pusher {
+    pub fn new(config: &PushConfig, server: &str) -> Pusher {
+        Pusher {
+            config: config.clone(),
+            server: String::from(server),
+            agent: ureq::AgentBuilder::new().timeout(config.timeout()).build(),
+            backoff: Duration::from_secs(1),
+            deadline: Instant::now(),
+            buffer: Mutex::new(VecDeque::new()),
+            statsd: StatsdState::default(),
+        }
+    }
+
+    // Errors are logged only, so that the next push is tried after the interval.
+    // `timestamp` is unix epoch in milliseconds.
+    // Sinks are pushed in parallel and retried until `deadline`, so that an unreachable sink doesn't delay
+    // other sinks and the next collection.
+    pub fn push(
+        &mut self,
+        metric_familys: &[MetricFamily],
+        snapshots: &[Snapshot],
+        timestamp: i64,
+        deadline: Instant,
+    ) {
+        self.deadline = deadline;
+        let influxdb = self
+            .config
+            .influxdb
+            .as_ref()
+            .map(|_| sink::influxdb(snapshots, &self.server, timestamp * 1_000_000));
+        let graphite = self.config.graphite.as_ref().map(|_| {
+            let prefix = self.config.graphite_prefix();
+            sink::graphite(snapshots, &prefix, &self.server, timestamp / 1000)
+        });
+        let statsd = if self.config.statsd.is_some() {
+            let dogstatsd = self.config.statsd_format != Some(StatsdFormat::Statsd);
+            let prefix = self.config.statsd_prefix();
+            Some(sink::statsd(
+                snapshots,
+                &prefix,
+                &self.server,
+                dogstatsd,
+                &mut self.statsd,
+            ))
+        } else {
+            None
+        };
+
+        let pusher = &*self;
+        let config = &self.config;
+        thread::scope(|s| {
+            if let Some(ref url) = config.pushgateway {
+                s.spawn(move || pusher.push_gateway(url, metric_familys));
+            }
+            if let Some(ref url) = config.remote_write {
+                s.spawn(move || pusher.remote_write(url, metric_familys, timestamp));
+            }
+            if let (Some(url), Some(lines)) = (&config.influxdb, &influxdb) {
+                s.spawn(move || pusher.influxdb(url, lines));
+            }
+            if let (Some(addr), Some(lines)) = (&config.graphite, &graphite) {
+                s.spawn(move || pusher.graphite(addr, lines));
+            }
+            if let (Some(addr), Some(lines)) = (&config.statsd, &statsd) {
+                s.spawn(move || pusher.statsd(addr, lines));
+            }
+        });
+    }
+
+    // Metrics are grouped by server and hub, so that the metrics of a failed hub don't remove others.
+    // PUT replaces all metrics in the group, so the metrics which disappeared are removed.
+    fn push_gateway(&self, url: &str, metric_familys: &[MetricFamily]) {
+        let job = self.config.job();
+        for (hub, metric_familys) in groups(metric_familys) {
+            let mut labels = vec![("server", self.server.as_str())];
+            if let Some(ref hub) = hub {
+                labels.push(("hub", hub));
+            }
+            let url = format!(
+                "{}{}",
+                url.trim_end_matches('/'),
+                grouping_path(&job, &labels)
+            );
+
+            let encoder = TextEncoder::new();
+            let mut body = vec![];
+            if let Err(x) = encoder.encode(&metric_familys, &mut body) {
+                warn!("Pushgateway encode failed: {}", x);
+                continue;
+            }
+            let request = self
+                .agent
+                .put(&url)
+                .set("Content-Type", encoder.format_type());
+            let ret = self.send("Pushgateway", request, &body);
+            if let Err(PushError::Retryable(x)) | Err(PushError::Rejected(x)) = ret {
+                warn!("Pushgateway push failed: {}", x);
+            }
+        }
+    }
+
+    fn remote_write(&self, url: &str, metric_familys: &[MetricFamily], timestamp: i64) {
+        let labels = [
+            ("job", self.config.job()),
+            ("instance", self.server.clone()),
+        ];
+        let request = WriteRequest {
+            timeseries: timeseries(metric_familys, &labels, timestamp),
+        };
+        let mut buffer = self.buffer.lock().unwrap();
+        if buffer.len() >= self.config.buffer() {
+            warn!("remote_write buffer is full: the oldest samples are dropped");
+            buffer.pop_front();
+        }
+        buffer.push_back(request);
+
+        // The buffered requests are sent in order, so that samples of each series are not out of order
+        while let Some(request) = buffer.front() {
+            let body = match snap::raw::Encoder::new().compress_vec(&request.encode_to_vec()) {
+                Ok(x) => x,
+                Err(x) => {
+                    warn!("remote_write encode failed: {}", x);
+                    buffer.pop_front();
+                    continue;
+                }
+            };
+            let request = self
+                .agent
+                .post(url)
+                .set("Content-Type", "application/x-protobuf")
+                .set("Content-Encoding", "snappy")
+                .set("X-Prometheus-Remote-Write-Version", "0.1.0");
+            let ret = self.send("remote_write", request, &body);
+            match ret {
+                Ok(_) => {
+                    buffer.pop_front();
+                    if !buffer.is_empty() && Instant::now() >= self.deadline {
+                        info!(
+                            "remote_write is deferred to the next push ( {} requests are buffered )",
+                            buffer.len()
+                        );
+                        break;
+                    }
+                }
+                Err(PushError::Rejected(x)) => {
+                    warn!("remote_write rejected: {}", x);
+                    buffer.pop_front();
+                }
+                Err(PushError::Retryable(x)) => {
+                    warn!(
+                        "remote_write failed: {} ( {} requests are buffered )",
+                        x,
+                        buffer.len()
+                    );
+                    break;
+                }
+            }
+        }
+    }
+
+    // "stdout" writes the lines for Telegraf execd input
+    fn influxdb(&self, url: &str, lines: &str) {
+        if url == "stdout" {
+            let mut stdout = io::stdout();
+            if let Err(x) = stdout
+                .write_all(lines.as_bytes())
+                .and_then(|_| stdout.flush())
+            {
+                warn!("InfluxDB write failed: {}", x);
+            }
+            return;
+        }
+
+        let mut request = self
+            .agent
+            .post(url)
+            .set("Content-Type", "text/plain; charset=utf-8");
+        if let Some(ref token) = self.config.influxdb_token {
+            request = request.set("Authorization", &format!("Token {}", token));
+        }
+        if let Err(PushError::Retryable(x)) | Err(PushError::Rejected(x)) =
+            self.send("InfluxDB", request, lines.as_bytes())
+        {
+            warn!("InfluxDB write failed: {}", x);
+        }
+    }
+
+    // A connection is made for each push, because Graphite closes idle connections
+    fn graphite(&self, addr: &str, lines: &str) {
+        let ret = self.retry("Graphite", || {
+            let addrs = addr
+                .to_socket_addrs()
+                .map_err(|x| PushError::Retryable(x.to_string()))?;
+            let mut last = PushError::Retryable(format!("{} is not resolved", addr));
+            for x in addrs {
+                let ret =
+                    TcpStream::connect_timeout(&x, self.config.timeout()).and_then(|mut x| {
+                        x.set_write_timeout(Some(self.config.timeout()))?;
+                        x.write_all(lines.as_bytes())
+                    });
+                match ret {
+                    Ok(_) => return Ok(()),
+                    Err(x) => last = PushError::Retryable(x.to_string()),
+                }
+            }
+            Err(last)
+        });
+        if let Err(PushError::Retryable(x)) | Err(PushError::Rejected(x)) = ret {
+            warn!("Graphite write failed: {}", x);
+        }
+    }
+
+    // UDP is not retried, and lines are packed into packets
+    fn statsd(&self, addr: &str, lines: &[String]) {
+        let ret = addr.to_socket_addrs().and_then(|mut addrs| {
+            let addr = addrs
+                .next()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "not resolved"))?;
+            let bind = if addr.is_ipv4() {
+                "0.0.0.0:0"
+            } else {
+                "[::]:0"
+            };
+            let socket = UdpSocket::bind(bind)?;
+            for packet in packets(lines, STATSD_PACKET_SIZE) {
+                socket.send_to(packet.as_bytes(), addr)?;
+            }
+            Ok(())
+        });
+        if let Err(x) = ret {
+            warn!("StatsD send failed: {}: {}", addr, x);
+        }
+    }
+
+    fn send(&self, name: &str, request: ureq::Request, body: &[u8]) -> Result<(), PushError> {
+        self.retry(name, || match request.clone().send_bytes(body) {
+            Ok(_) => Ok(()),
+            Err(ureq::Error::Status(code, res)) => {
+                let body = res.into_string().unwrap_or_default();
+                let msg = format!("{} {}", code, body.trim());
+                if code != 429 && code < 500 {
+                    Err(PushError::Rejected(msg))
+                } else {
+                    Err(PushError::Retryable(msg))
+                }
+            }
+            Err(x) => Err(PushError::Retryable(x.to_string())),
+        })
+    }
+
+    // Retry with exponential backoff, unless the retry is after the deadline
+    fn retry<F>(&self, name: &str, f: F) -> Result<(), PushError>
+    where
+        F: Fn() -> Result<(), PushError>,
+    {
+        let mut backoff = self.backoff;
+        let mut attempt = 0;
+        loop {
+            let err = match f() {
+                Ok(_) => return Ok(()),
+                Err(PushError::Rejected(x)) => return Err(PushError::Rejected(x)),
+                Err(PushError::Retryable(x)) => x,
+            };
+            if attempt >= self.config.retries() || Instant::now() + backoff > self.deadline {
+                return Err(PushError::Retryable(err));
+            }
+            attempt += 1;
+            info!(
+                "{} push failed: {} ( retry {} in {:?} )",
+                name, err, attempt, backoff
+            );
+            thread::sleep(backoff);
+            backoff *= 2;
+        }
+    }
+}
+
+// Lines are joined by newline into packets not exceeding `size` ( except a line longer than it )
+fn packets(lines: &[String], size: usize) -> Vec<String> {
+    let mut packets = Vec::new();
+    let mut packet = String::new();
+    for line in lines {
+        if !packet.is_empty() && packet.len() + 1 + line.len() > size {
+            packets.push(packet);
+            packet = String::new();
+        }
+        if !packet.is_empty() {
+            packet.push('\n');
+        }
+        packet.push_str(line);
+    }
+    if !packet.is_empty() {
+        packets.push(packet);
+    }
+    packets
+}
+
+// Metrics are split by hub label. Metrics without hub ( or with empty hub ) belong to the server group.
+fn groups(metric_familys: &[MetricFamily]) -> BTreeMap<Option<String>, Vec<MetricFamily>> {
+    let mut groups: BTreeMap<Option<String>, Vec<MetricFamily>> = BTreeMap::new();
+    for mf in metric_familys {
+        let mut split: BTreeMap<Option<String>, MetricFamily> = BTreeMap::new();
+        for m in mf.get_metric() {
+            let hub = m
+                .get_label()
+                .iter()
+                .find(|x| x.name() == "hub" && !x.value().is_empty())
+                .map(|x| String::from(x.value()));
+            split
+                .entry(hub)
+                .or_insert_with(|| {
+                    let mut x = mf.clone();
+                    x.mut_metric().clear();
+                    x
+                })
+                .mut_metric()
+                .push(m.clone());
+        }
+        for (hub, mf) in split {
+            groups.entry(hub).or_default().push(mf);
+        }
+    }
+    groups
+}
+
+// Label values which can't be used in URL path are encoded by base64 as Pushgateway specifies
+fn grouping_path(job: &str, labels: &[(&str, &str)]) -> String {
+    let segment = |name: &str, value: &str| {
+        let safe = !value.is_empty()
+            && value
+                .chars()
+                .all(|x| x.is_ascii_alphanumeric() || "-_.:".contains(x));
+        if safe {
+            format!("/{}/{}", name, value)
+        } else if value.is_empty() {
+            format!("/{}@base64/=", name)
+        } else {
+            format!("/{}@base64/{}", name, URL_SAFE.encode(value))
+        }
+    };
+    let mut path = format!("/metrics{}", segment("job", job));
+    for (name, value) in labels {
+        path.push_str(&segment(name, value));
+    }
+    path
+}
+
+fn timeseries(
+    metric_familys: &[MetricFamily],
+    labels: &[(&str, String)],
+    timestamp: i64,
+) -> Vec<TimeSeries> {
+    let mut ret = Vec::new();
+    for mf in metric_familys {
+        let name = mf.name();
+        for m in mf.get_metric() {
+            let mut push = |suffix: &str, additional: Option<(&str, String)>, value: f64| {
+                let mut pairs: Vec<(String, String)> = labels
+                    .iter()
+                    .map(|(x, y)| (String::from(*x), y.clone()))
+                    .collect();
+                pairs.push((String::from("__name__"), format!("{}{}", name, suffix)));
+                for label in m.get_label() {
+                    pairs.push((String::from(label.name()), String::from(label.value())));
+                }
+                if let Some((x, y)) = additional {
+                    pairs.push((String::from(x), y));
+                }
+                // Labels must be sorted by name in remote_write
+                pairs.sort();
+                ret.push(TimeSeries {
+                    labels: pairs
+                        .into_iter()
+                        .map(|(name, value)| Label { name, value })
+                        .collect(),
+                    samples: vec![Sample { value, timestamp }],
+                });
+            };
+            match mf.get_field_type() {
+                MetricType::COUNTER => push("", None, m.get_counter().value()),
+                MetricType::GAUGE => push("", None, m.get_gauge().value()),
+                MetricType::UNTYPED => push("", None, m.untyped.value()),
+                MetricType::HISTOGRAM => {
+                    let h = m.get_histogram();
+                    let mut inf_seen = false;
+                    for b in h.get_bucket() {
+                        let le = format_float(b.upper_bound());
+                        push("_bucket", Some(("le", le)), b.cumulative_count() as f64);
+                        inf_seen |= b.upper_bound() == f64::INFINITY;
+                    }
+                    let count = h.get_sample_count() as f64;
+                    if !inf_seen {
+                        push("_bucket", Some(("le", String::from("+Inf"))), count);
+                    }
+                    push("_count", None, count);
+                    push("_sum", None, h.get_sample_sum());
+                }
+                MetricType::SUMMARY => {
+                    let s = m.get_summary();
+                    for q in s.get_quantile() {
+                        let quantile = format_float(q.quantile());
+                        push("", Some(("quantile", quantile)), q.value());
+                    }
+                    push("_count", None, s.sample_count() as f64);
+                    push("_sum", None, s.sample_sum());
+                }
+            }
+        }
+    }
+    ret
+}
+
+// Same as the text format of Prometheus
+fn format_float(value: f64) -> String {
+    if value == f64::INFINITY {
+        String::from("+Inf")
+    } else if value == f64::NEG_INFINITY {
+        String::from("-Inf")
+    } else {
+        value.to_string()
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Test
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::{self, Body};
+    use crate::softether_reader::{HubSession, HubStatus};
+    use http_body_util::BodyExt;
+    use hyper::body::Incoming;
+    use hyper::{Request, Response, StatusCode};
+    use prometheus::{CounterVec, GaugeVec, Histogram, HistogramOpts, Opts, Registry};
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex};
+    use tokio::net::TcpListener;
+    use tokio::runtime::Runtime;
+
+    // Received request: method, path, content-encoding and body
+    type Received = Arc<Mutex<Vec<(String, String, String, Vec<u8>)>>>;
+
+    // Local HTTP server standing in for Pushgateway / remote_write endpoint.
+    // It responds with the given statuses in order, and 200 after them.
+    fn stand_in(statuses: Vec<StatusCode>) -> (Runtime, SocketAddr, Received) {
+        let runtime = Runtime::new().unwrap();
+        let listener = runtime.block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let received: Received = Arc::new(Mutex::new(Vec::new()));
+        let statuses = Arc::new(Mutex::new(VecDeque::from(statuses)));
+
+        let handler = {
+            let received = received.clone();
+            move |req: Request<Incoming>| {
+                let received = received.clone();
+                let statuses = statuses.clone();
+                async move {
+                    let method = req.method().to_string();
+                    let path = req.uri().path().to_string();
+                    let encoding = req
+                        .headers()
+                        .get("content-encoding")
+                        .and_then(|x| x.to_str().ok())
+                        .unwrap_or("")
+                        .to_string();
+                    let body = req.into_body().collect().await.unwrap().to_bytes();
+                    received
+                        .lock()
+                        .unwrap()
+                        .push((method, path, encoding, body.to_vec()));
+                    let status = statuses.lock().unwrap().pop_front();
+                    let res: Response<Body> =
+                        server::response(status.unwrap_or(StatusCode::OK), "text/plain", "");
+                    res
+                }
+            }
+        };
+        runtime.spawn(server::serve(
+            listener,
+            None,
+            Duration::from_secs(10),
+            handler,
+            std::future::pending(),
+        ));
+        (runtime, addr, received)
+    }
+
+    fn snapshot() -> Vec<MetricFamily> {
+        let registry = Registry::new();
+        let up = GaugeVec::new(Opts::new("softether_up", "Up"), &["hub"]).unwrap();
+        let errors = CounterVec::new(
+            Opts::new("softether_scrape_errors_total", "Errors"),
+            &["hub", "kind"],
+        )
+        .unwrap();
+        let duration = Histogram::with_opts(
+            HistogramOpts::new("softether_scrape_duration_seconds", "Duration").buckets(vec![1.0]),
+        )
+        .unwrap();
+        registry.register(Box::new(up.clone())).unwrap();
+        registry.register(Box::new(errors.clone())).unwrap();
+        registry.register(Box::new(duration.clone())).unwrap();
+
+        up.with_label_values(&["HUB1"]).set(1.0);
+        up.with_label_values(&["HUB/2"]).set(0.0);
+        errors.with_label_values(&["", "unreachable"]).inc();
+        duration.observe(0.5);
+        registry.gather()
+    }
+
+    fn config(pushgateway: Option<String>, remote_write: Option<String>) -> PushConfig {
+        PushConfig {
+            interval: None,
+            job: None,
+            pushgateway,
+            remote_write,
+            influxdb: None,
+            influxdb_token: None,
+            graphite: None,
+            graphite_prefix: None,
+            statsd: None,
+            statsd_prefix: None,
+            statsd_format: None,
+            timeout: None,
+            retries: Some(2),
+            buffer: Some(2),
+        }
+    }
+
+    fn deadline() -> Instant {
+        Instant::now() + Duration::from_secs(60)
+    }
+
+    fn labels(ts: &TimeSeries) -> Vec<(&str, &str)> {
+        ts.labels
+            .iter()
+            .map(|x| (x.name.as_str(), x.value.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn test_grouping_path() {
+        assert_eq!(
+            grouping_path(
+                "softether",
+                &[("server", "localhost:8888"), ("hub", "HUB1")]
+            ),
+            "/metrics/job/softether/server/localhost:8888/hub/HUB1"
+        );
+        assert_eq!(
+            grouping_path("softether", &[("server", "vpn"), ("hub", "HUB/2")]),
+            "/metrics/job/softether/server/vpn/hub@base64/SFVCLzI="
+        );
+        assert_eq!(
+            grouping_path("softether", &[("server", "")]),
+            "/metrics/job/softether/server@base64/="
+        );
+    }
+
+    #[test]
+    fn test_timeseries() {
+        let ret = timeseries(
+            &snapshot(),
+            &[
+                ("instance", String::from("vpn")),
+                ("job", String::from("softether")),
+            ],
+            1600000000000,
+        );
+        let ret: Vec<(Vec<(&str, &str)>, f64)> = ret
+            .iter()
+            .map(|x| {
+                assert_eq!(x.samples[0].timestamp, 1600000000000);
+                (labels(x), x.samples[0].value)
+            })
+            .collect();
+        fn series(
+            name: &'static str,
+            extra: &[(&'static str, &'static str)],
+        ) -> Vec<(&'static str, &'static str)> {
+            let mut x = vec![
+                ("__name__", name),
+                ("instance", "vpn"),
+                ("job", "softether"),
+            ];
+            x.extend_from_slice(extra);
+            x.sort();
+            x
+        }
+        assert_eq!(
+            ret,
+            vec![
+                (
+                    series("softether_scrape_duration_seconds_bucket", &[("le", "1")]),
+                    1.0
+                ),
+                (
+                    series(
+                        "softether_scrape_duration_seconds_bucket",
+                        &[("le", "+Inf")]
+                    ),
+                    1.0
+                ),
+                (series("softether_scrape_duration_seconds_count", &[]), 1.0),
+                (series("softether_scrape_duration_seconds_sum", &[]), 0.5),
+                (
+                    series(
+                        "softether_scrape_errors_total",
+                        &[("hub", ""), ("kind", "unreachable")]
+                    ),
+                    1.0
+                ),
+                (series("softether_up", &[("hub", "HUB/2")]), 0.0),
+                (series("softether_up", &[("hub", "HUB1")]), 1.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pushgateway() {
+        let (_runtime, addr, received) = stand_in(vec![StatusCode::SERVICE_UNAVAILABLE]);
+        let mut pusher = Pusher::new(
+            &config(Some(format!("http://{}/", addr)), None),
+            "localhost:8888",
+        );
+        pusher.backoff = Duration::from_millis(10);
+        pusher.push(&snapshot(), &[], 0, deadline());
+
+        let received = received.lock().unwrap();
+        let paths: Vec<&str> = received.iter().map(|x| x.1.as_str()).collect();
+        // The first push of the server group is retried after 503
+        assert_eq!(
+            paths,
+            vec![
+                "/metrics/job/softether/server/localhost:8888",
+                "/metrics/job/softether/server/localhost:8888",
+                "/metrics/job/softether/server/localhost:8888/hub@base64/SFVCLzI=",
+                "/metrics/job/softether/server/localhost:8888/hub/HUB1",
+            ]
+        );
+        assert!(received.iter().all(|x| x.0 == "PUT"));
+
+        let body = String::from_utf8(received[1].3.clone()).unwrap();
+        assert!(body.contains("softether_scrape_errors_total{hub=\"\",kind=\"unreachable\"} 1"));
+        assert!(body.contains("softether_scrape_duration_seconds_count 1"));
+        assert!(!body.contains("softether_up"));
+        let body = String::from_utf8(received[3].3.clone()).unwrap();
+        assert_eq!(
+            body,
+            "# HELP softether_up Up\n# TYPE softether_up gauge\nsoftether_up{hub=\"HUB1\"} 1\n"
+        );
+    }
+
+    #[test]
+    fn test_remote_write() {
+        // All attempts ( 1 + 2 retries ) of the first push fail
+        let (_runtime, addr, received) = stand_in(vec![StatusCode::SERVICE_UNAVAILABLE; 3]);
+        let mut pusher = Pusher::new(
+            &config(None, Some(format!("http://{}/api/v1/write", addr))),
+            "vpn",
+        );
+        pusher.backoff = Duration::from_millis(10);
+
+        pusher.push(&snapshot(), &[], 1000, deadline());
+        assert_eq!(pusher.buffer.lock().unwrap().len(), 1);
+        assert_eq!(received.lock().unwrap().len(), 3);
+
+        // The buffered request is sent before the new one
+        pusher.push(&snapshot(), &[], 2000, deadline());
+        assert!(pusher.buffer.lock().unwrap().is_empty());
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 5);
+
+        let timestamps: Vec<i64> = received[3..]
+            .iter()
+            .map(|(method, path, encoding, body)| {
+                assert_eq!(method, "POST");
+                assert_eq!(path, "/api/v1/write");
+                assert_eq!(encoding, "snappy");
+                let body = snap::raw::Decoder::new().decompress_vec(body).unwrap();
+                let request = WriteRequest::decode(body.as_slice()).unwrap();
+                assert_eq!(request.timeseries.len(), 7);
+                assert_eq!(
+                    labels(&request.timeseries[6]),
+                    vec![
+                        ("__name__", "softether_up"),
+                        ("hub", "HUB1"),
+                        ("instance", "vpn"),
+                        ("job", "softether")
+                    ]
+                );
+                request.timeseries[0].samples[0].timestamp
+            })
+            .collect();
+        assert_eq!(timestamps, vec![1000, 2000]);
+    }
+
+    #[test]
+    fn test_remote_write_buffer() {
+        let (_runtime, addr, received) = stand_in(vec![StatusCode::SERVICE_UNAVAILABLE; 9]);
+        let mut pusher = Pusher::new(&config(None, Some(format!("http://{}/", addr))), "vpn");
+        pusher.backoff = Duration::from_millis(1);
+
+        // The buffer size is 2, so the oldest is dropped
+        pusher.push(&snapshot(), &[], 1000, deadline());
+        pusher.push(&snapshot(), &[], 2000, deadline());
+        pusher.push(&snapshot(), &[], 3000, deadline());
+        assert_eq!(pusher.buffer.lock().unwrap().len(), 2);
+        assert_eq!(
+            pusher.buffer.lock().unwrap()[0].timeseries[0].samples[0].timestamp,
+            2000
+        );
+        assert_eq!(received.lock().unwrap().len(), 9);
+    }
+
+    #[test]
+    fn test_rejected() {
+        // 400 is not retried, and the request is dropped
+        let (_runtime, addr, received) = stand_in(vec![StatusCode::BAD_REQUEST]);
+        let mut pusher = Pusher::new(&config(None, Some(format!("http://{}/", addr))), "vpn");
+        pusher.backoff = Duration::from_millis(1);
+        pusher.push(&snapshot(), &[], 1000, deadline());
+        assert!(pusher.buffer.lock().unwrap().is_empty());
+        assert_eq!(received.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_deadline() {
+        // Retries after the deadline are not waited, and the request is kept in the buffer
+        let (_runtime, addr, received) = stand_in(vec![StatusCode::SERVICE_UNAVAILABLE; 9]);
+        let mut config = config(None, Some(format!("http://{}/", addr)));
+        config.retries = Some(5);
+        let mut pusher = Pusher::new(&config, "vpn");
+        let start = Instant::now();
+        pusher.push(
+            &snapshot(),
+            &[],
+            1000,
+            Instant::now() + Duration::from_millis(500),
+        );
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(received.lock().unwrap().len(), 1);
+        assert_eq!(pusher.buffer.lock().unwrap().len(), 1);
+    }
+
+    fn hub_snapshot() -> Vec<Snapshot> {
+        let mut status = HubStatus::new();
+        status.name = String::from("HUB1");
+        vec![(status, vec![])]
+    }
+
+    #[test]
+    fn test_influxdb() {
+        let (_runtime, addr, received) = stand_in(vec![StatusCode::SERVICE_UNAVAILABLE]);
+        let mut config = config(None, None);
+        config.influxdb = Some(format!(
+            "http://{}/api/v2/write?org=org&bucket=softether",
+            addr
+        ));
+        let mut pusher = Pusher::new(&config, "vpn");
+        pusher.backoff = Duration::from_millis(1);
+        pusher.push(&[], &hub_snapshot(), 1600000000000, deadline());
+
+        // Retried after 503
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[1].0, "POST");
+        assert_eq!(received[1].1, "/api/v2/write");
+        let body = String::from_utf8(received[1].3.clone()).unwrap();
+        assert_eq!(
+            body,
+            sink::influxdb(&hub_snapshot(), "vpn", 1600000000000000000)
+        );
+        assert!(body.starts_with("softether_hub,server=vpn,hub=HUB1,type=unknown online=0,"));
+    }
+
+    #[test]
+    fn test_graphite() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let receiver = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = String::new();
+            std::io::Read::read_to_string(&mut stream, &mut buf).unwrap();
+            buf
+        });
+
+        let mut config = config(None, None);
+        config.graphite = Some(addr.to_string());
+        config.graphite_prefix = Some(String::from("vpn"));
+        let mut pusher = Pusher::new(&config, "localhost:8888");
+        pusher.push(&[], &hub_snapshot(), 1600000000000, deadline());
+
+        let received = receiver.join().unwrap();
+        assert_eq!(
+            received,
+            sink::graphite(&hub_snapshot(), "vpn", "localhost:8888", 1600000000)
+        );
+        assert!(received.starts_with("vpn.localhost_8888.HUB1.online 0 1600000000\n"));
+    }
+
+    #[test]
+    fn test_statsd() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut config = config(None, None);
+        config.statsd = Some(receiver.local_addr().unwrap().to_string());
+        let mut pusher = Pusher::new(&config, "vpn");
+
+        let mut snapshots = hub_snapshot();
+        snapshots[0].1 = (0..50)
+            .map(|x| {
+                let user = format!("user{:02}", x);
+                HubSession::new(&format!("SID-{}", user), &user, 100.0, 1.0)
+            })
+            .collect();
+        pusher.push(&[], &snapshots, 0, deadline());
+        for session in &mut snapshots[0].1 {
+            session.transfer_bytes += 50.0;
+        }
+        pusher.push(&[], &snapshots, 0, deadline());
+
+        let mut received = Vec::new();
+        let mut buf = [0; 65536];
+        // 19 hub gauges + 50 user gauges, then the same + 100 user counters
+        while received.len() < 19 + 50 + 19 + 50 * 3 {
+            let len = receiver.recv(&mut buf).unwrap();
+            assert!(len <= STATSD_PACKET_SIZE);
+            let packet = String::from_utf8(buf[..len].to_vec()).unwrap();
+            received.extend(packet.lines().map(String::from));
+        }
+        assert_eq!(received[0], "softether.hub.online:0|g|#server:vpn,hub:HUB1");
+        assert!(received.contains(&String::from(
+            "softether.user.transfer_bytes:50|c|#server:vpn,hub:HUB1,user:user49"
+        )));
+        assert!(!received
+            .iter()
+            .any(|x| x.starts_with("softether.user.transfer_bytes:100|c")));
+    }
+
+    #[test]
+    fn test_packets() {
+        let lines: Vec<String> = vec![
+            "a".repeat(5),
+            "b".repeat(4),
+            "c".repeat(12),
+            "d".to_string(),
+        ];
+        assert_eq!(
+            packets(&lines, 10),
+            vec!["aaaaa\nbbbb", "cccccccccccc", "d"]
+        );
+        assert!(packets(&[], 10).is_empty());
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(config(None, None).validate().is_err());
+        assert!(config(Some(String::from("pushgateway:9091")), None)
+            .validate()
+            .is_err());
+        assert!(config(Some(String::from("http://pushgateway:9091")), None)
+            .validate()
+            .is_ok());
+        let mut influxdb = config(None, None);
+        influxdb.influxdb = Some(String::from("stdout"));
+        assert!(influxdb.validate().is_ok());
+        influxdb.influxdb = Some(String::from("influxdb:8086"));
+        assert!(influxdb.validate().is_err());
+    }
+}
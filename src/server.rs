@@ -0,0 +1,204 @@
+use anyhow::Error;
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::body::Incoming;
+use hyper::header::CONTENT_TYPE;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Request, Response, StatusCode};
+use hyper_util::rt::{TokioIo, TokioTimer};
+use hyper_util::server::graceful::{GracefulShutdown, Watcher};
+use log::{debug, warn};
+use std::convert::Infallible;
+use std::future::Future;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio::time::timeout;
+use tokio_rustls::TlsAcceptor;
+
+pub type Body = Full<Bytes>;
+
+static HEADER_READ_TIMEOUT: u64 = 10;
+static HANDSHAKE_TIMEOUT: u64 = 10;
+static SHUTDOWN_TIMEOUT: u64 = 30;
+
+// -------------------------------------------------------------------------------------------------
+// Server
+// -------------------------------------------------------------------------------------------------
+
+// Serve HTTP ( or HTTPS if `tls` is given ) until `shutdown` is completed.
+// Requests which are not completed in `request_timeout` get 503.
+pub async fn serve<H, F, S>(
+    listener: TcpListener,
+    tls: Option<TlsAcceptor>,
+    request_timeout: Duration,
+    handler: H,
+    shutdown: S,
+) -> Result<(), Error>
+where
+    H: Fn(Request<Incoming>) -> F + Clone + Send + Sync + 'static,
+    F: Future<Output = Response<Body>> + Send + 'static,
+    S: Future<Output = ()>,
+{
+    let graceful = GracefulShutdown::new();
+    tokio::pin!(shutdown);
+
+    loop {
+        let stream = tokio::select! {
+            x = listener.accept() => match x {
+                Ok((stream, _)) => stream,
+                Err(x) => {
+                    warn!("Accept failed: {}", x);
+                    continue;
+                }
+            },
+            _ = &mut shutdown => break,
+        };
+
+        let handler = handler.clone();
+        let tls = tls.clone();
+        let watcher = graceful.watcher();
+        tokio::spawn(async move {
+            let handler = move |req| {
+                let res = timeout(request_timeout, handler(req));
+                async move {
+                    let res = res.await.unwrap_or_else(|_| {
+                        response(
+                            StatusCode::SERVICE_UNAVAILABLE,
+                            "text/plain",
+                            "Request timeout\n",
+                        )
+                    });
+                    Ok::<_, Infallible>(res)
+                }
+            };
+            match tls {
+                Some(tls) => {
+                    let handshake =
+                        timeout(Duration::from_secs(HANDSHAKE_TIMEOUT), tls.accept(stream));
+                    match handshake.await {
+                        Ok(Ok(stream)) => serve_connection(stream, handler, watcher).await,
+                        Ok(Err(x)) => debug!("TLS handshake failed: {}", x),
+                        Err(_) => debug!("TLS handshake failed: timeout"),
+                    }
+                }
+                None => serve_connection(stream, handler, watcher).await,
+            }
+        });
+    }
+
+    // In-flight requests ( e.g. running vpncmd ) are waited for a while
+    tokio::select! {
+        _ = graceful.shutdown() => (),
+        _ = tokio::time::sleep(Duration::from_secs(SHUTDOWN_TIMEOUT)) => {
+            warn!("Shutdown timeout: in-flight requests are aborted");
+        }
+    }
+    Ok(())
+}
+
+async fn serve_connection<S, H, F>(stream: S, handler: H, watcher: Watcher)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    H: Fn(Request<Incoming>) -> F + Send + 'static,
+    F: Future<Output = Result<Response<Body>, Infallible>> + Send + 'static,
+{
+    let conn = http1::Builder::new()
+        .timer(TokioTimer::new())
+        .header_read_timeout(Duration::from_secs(HEADER_READ_TIMEOUT))
+        .serve_connection(TokioIo::new(stream), service_fn(handler));
+    let _ = watcher.watch(conn).await;
+}
+
+// Wait for SIGTERM or Ctrl-C
+pub async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut sigterm = signal(SignalKind::terminate()).ok();
+        let sigterm = async {
+            match sigterm {
+                Some(ref mut x) => {
+                    x.recv().await;
+                }
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            _ = sigterm => (),
+            _ = tokio::signal::ctrl_c() => (),
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+pub fn response<T: Into<Bytes>>(status: StatusCode, content_type: &str, body: T) -> Response<Body> {
+    let mut res = Response::new(Full::new(body.into()));
+    *res.status_mut() = status;
+    if let Ok(x) = content_type.parse() {
+        res.headers_mut().insert(CONTENT_TYPE, x);
+    }
+    res
+}
+
+// -------------------------------------------------------------------------------------------------
+// Test
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpStream};
+    use tokio::runtime::Runtime;
+    use tokio::sync::oneshot;
+
+    // Send a request by HTTP/1.0 and return the whole response
+    pub fn request(addr: SocketAddr, method: &str, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "{} {} HTTP/1.0\r\n\r\n", method, path).unwrap();
+        let mut buf = String::new();
+        stream.read_to_string(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_serve() {
+        let runtime = Runtime::new().unwrap();
+        let listener = runtime.block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (tx, rx) = oneshot::channel::<()>();
+
+        let handler = |req: Request<Incoming>| async move {
+            if req.uri().path() == "/slow" {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+            }
+            response(StatusCode::OK, "text/plain", "ok")
+        };
+        let server = runtime.spawn(serve(
+            listener,
+            None,
+            Duration::from_millis(100),
+            handler,
+            async {
+                let _ = rx.await;
+            },
+        ));
+
+        let ret = request(addr, "GET", "/");
+        assert!(ret.starts_with("HTTP/1.0 200 OK"));
+        assert!(ret.contains("content-type: text/plain"));
+        assert!(ret.ends_with("ok"));
+
+        let ret = request(addr, "GET", "/slow");
+        assert!(ret.starts_with("HTTP/1.0 503 Service Unavailable"));
+
+        tx.send(()).unwrap();
+        runtime.block_on(server).unwrap().unwrap();
+        assert!(TcpStream::connect(addr).is_err());
+    }
+}
@@ -0,0 +1,161 @@
+use crate::softether_reader::{HubSession, SessionProtocol};
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+
+// -------------------------------------------------------------------------------------------------
+// SessionEvent
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EventKind {
+    Connect,
+    Disconnect,
+}
+
+// Login / logout detected by the difference of SessionList between collections.
+// So `time` is the time of the collection, not the exact time of the login / logout.
+#[derive(Debug, Serialize)]
+pub struct SessionEvent {
+    pub time: DateTime<Local>,
+    pub event: EventKind,
+    pub hub: String,
+    pub session: String,
+    pub user: String,
+    pub source: String,
+    pub protocol: SessionProtocol,
+    // The transfer at the last collection before the logout
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transfer_bytes: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transfer_packets: Option<f64>,
+}
+
+impl SessionEvent {
+    fn new(time: DateTime<Local>, event: EventKind, hub: &str, session: &HubSession) -> Self {
+        let disconnect = event == EventKind::Disconnect;
+        SessionEvent {
+            time,
+            event,
+            hub: String::from(hub),
+            session: session.name.clone(),
+            user: session.user.clone(),
+            source: session.source.clone(),
+            protocol: session.protocol,
+            transfer_bytes: if disconnect {
+                Some(session.transfer_bytes)
+            } else {
+                None
+            },
+            transfer_packets: if disconnect {
+                Some(session.transfer_packets)
+            } else {
+                None
+            },
+        }
+    }
+}
+
+// Sessions are identified by the session name, which is unique in the hub.
+// Disconnects are listed before connects, so that a reconnect of the same user is in order.
+pub fn diff(
+    hub: &str,
+    last: &[HubSession],
+    current: &[HubSession],
+    time: DateTime<Local>,
+) -> Vec<SessionEvent> {
+    let last_names: HashSet<&str> = last.iter().map(|x| x.name.as_str()).collect();
+    let current_names: HashSet<&str> = current.iter().map(|x| x.name.as_str()).collect();
+
+    let disconnects = last
+        .iter()
+        .filter(|x| !current_names.contains(x.name.as_str()))
+        .map(|x| SessionEvent::new(time, EventKind::Disconnect, hub, x));
+    let connects = current
+        .iter()
+        .filter(|x| !last_names.contains(x.name.as_str()))
+        .map(|x| SessionEvent::new(time, EventKind::Connect, hub, x));
+    disconnects.chain(connects).collect()
+}
+
+// Events are appended as JSON lines. The file is opened at each write, so that it can be rotated.
+pub fn append(path: &Path, events: &[SessionEvent]) -> Result<(), io::Error> {
+    if events.is_empty() {
+        return Ok(());
+    }
+    let mut buf = Vec::new();
+    for event in events {
+        serde_json::to_writer(&mut buf, event)?;
+        buf.push(b'\n');
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(&buf)
+}
+
+// -------------------------------------------------------------------------------------------------
+// Test
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::env;
+    use std::fs;
+
+    fn session(name: &str, user: &str, bytes: f64) -> HubSession {
+        HubSession::new(name, user, bytes, 10.0)
+    }
+
+    #[test]
+    fn test_diff() {
+        let time = Local.timestamp_opt(1600000000, 0).unwrap();
+        let last = vec![
+            session("SID-1", "user1", 100.0),
+            session("SID-2", "user2", 200.0),
+        ];
+        let current = vec![
+            session("SID-2", "user2", 300.0),
+            session("SID-3", "user1", 0.0),
+        ];
+
+        let events = diff("HUB1", &last, &current, time);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].event, EventKind::Disconnect);
+        assert_eq!(events[0].session, "SID-1");
+        assert_eq!(events[0].transfer_bytes, Some(100.0));
+        assert_eq!(events[1].event, EventKind::Connect);
+        assert_eq!(events[1].session, "SID-3");
+        assert_eq!(events[1].transfer_bytes, None);
+
+        assert!(diff("HUB1", &current, &current, time).is_empty());
+    }
+
+    #[test]
+    fn test_append() {
+        let time = Local.timestamp_opt(1600000000, 0).unwrap();
+        let path = env::temp_dir().join("softether_exporter_session_event.jsonl");
+        let _ = fs::remove_file(&path);
+
+        let last = vec![session("SID-USER1-[OPENVPN_L3]-1", "user1", 1024.0)];
+        let events = diff("HUB1", &last, &[], time);
+        append(&path, &events).unwrap();
+        let events = diff("HUB1", &[], &last, time);
+        append(&path, &events).unwrap();
+        append(&path, &[]).unwrap();
+
+        let ts = serde_json::to_string(&time).unwrap();
+        let expected = format!(
+            r#"{{"time":{},"event":"disconnect","hub":"HUB1","session":"SID-USER1-[OPENVPN_L3]-1","user":"user1","source":"192.168.0.1","protocol":"openvpn","transfer_bytes":1024.0,"transfer_packets":10.0}}
+{{"time":{},"event":"connect","hub":"HUB1","session":"SID-USER1-[OPENVPN_L3]-1","user":"user1","source":"192.168.0.1","protocol":"openvpn"}}
+"#,
+            ts, ts
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap(), expected);
+        fs::remove_file(&path).unwrap();
+    }
+}
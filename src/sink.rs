@@ -0,0 +1,444 @@
+use crate::softether_reader::{HubSession, HubStatus};
+use std::collections::{BTreeMap, HashMap};
+
+// -------------------------------------------------------------------------------------------------
+// Snapshot
+// -------------------------------------------------------------------------------------------------
+
+// Sinks for non-Prometheus systems are encoded from the collected HubStatus and HubSession
+// directly, instead of the metrics of the registry.
+pub type Snapshot = (HubStatus, Vec<HubSession>);
+
+fn hub_fields(status: &HubStatus) -> Vec<(&'static str, f64)> {
+    let bool = |x: bool| if x { 1.0 } else { 0.0 };
+    vec![
+        ("online", bool(status.online)),
+        ("secure_nat", bool(status.secure_nat)),
+        ("sessions", status.sessions),
+        ("sessions_client", status.sessions_client),
+        ("sessions_bridge", status.sessions_bridge),
+        ("access_lists", status.access_lists),
+        ("users", status.users),
+        ("groups", status.groups),
+        ("mac_tables", status.mac_tables),
+        ("ip_tables", status.ip_tables),
+        ("logins", status.logins),
+        ("outgoing_unicast_packets", status.outgoing_unicast_packets),
+        ("outgoing_unicast_bytes", status.outgoing_unicast_bytes),
+        (
+            "outgoing_broadcast_packets",
+            status.outgoing_broadcast_packets,
+        ),
+        ("outgoing_broadcast_bytes", status.outgoing_broadcast_bytes),
+        ("incoming_unicast_packets", status.incoming_unicast_packets),
+        ("incoming_unicast_bytes", status.incoming_unicast_bytes),
+        (
+            "incoming_broadcast_packets",
+            status.incoming_broadcast_packets,
+        ),
+        ("incoming_broadcast_bytes", status.incoming_broadcast_bytes),
+    ]
+}
+
+// Transfer of each user is the total of the sessions, as softether_user_transfer_*
+fn user_fields(sessions: &[HubSession]) -> BTreeMap<&str, Vec<(&'static str, f64)>> {
+    let mut users: BTreeMap<&str, (f64, f64, f64)> = BTreeMap::new();
+    for session in sessions {
+        let user = users.entry(&session.user).or_insert((0.0, 0.0, 0.0));
+        user.0 += 1.0;
+        user.1 += session.transfer_bytes;
+        user.2 += session.transfer_packets;
+    }
+    users
+        .into_iter()
+        .map(|(user, (sessions, bytes, packets))| {
+            let fields = vec![
+                ("sessions", sessions),
+                ("transfer_bytes", bytes),
+                ("transfer_packets", packets),
+            ];
+            (user, fields)
+        })
+        .collect()
+}
+
+// -------------------------------------------------------------------------------------------------
+// InfluxDB line protocol
+// -------------------------------------------------------------------------------------------------
+
+pub fn influxdb(snapshots: &[Snapshot], server: &str, timestamp_ns: i64) -> String {
+    let mut lines = String::new();
+    for (status, sessions) in snapshots {
+        let tags = format!(
+            "server={},hub={}",
+            influxdb_escape(server),
+            influxdb_escape(&status.name)
+        );
+        lines.push_str(&influxdb_line(
+            "softether_hub",
+            &format!("{},type={}", tags, status.hub_type.as_str()),
+            &hub_fields(status),
+            timestamp_ns,
+        ));
+        for (user, fields) in user_fields(sessions) {
+            lines.push_str(&influxdb_line(
+                "softether_user",
+                &format!("{},user={}", tags, influxdb_escape(user)),
+                &fields,
+                timestamp_ns,
+            ));
+        }
+    }
+    lines
+}
+
+fn influxdb_line(measurement: &str, tags: &str, fields: &[(&str, f64)], timestamp: i64) -> String {
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect();
+    format!(
+        "{},{} {} {}\n",
+        measurement,
+        tags,
+        fields.join(","),
+        timestamp
+    )
+}
+
+// Comma, equal, space and backslash in tag values are escaped, and newline is not allowed
+fn influxdb_escape(s: &str) -> String {
+    let mut ret = String::new();
+    for c in s.chars() {
+        match c {
+            ',' | '=' | ' ' | '\\' => {
+                ret.push('\\');
+                ret.push(c);
+            }
+            '\n' => ret.push(' '),
+            _ => ret.push(c),
+        }
+    }
+    ret
+}
+
+// -------------------------------------------------------------------------------------------------
+// Graphite plaintext protocol
+// -------------------------------------------------------------------------------------------------
+
+pub fn graphite(snapshots: &[Snapshot], prefix: &str, server: &str, timestamp: i64) -> String {
+    let mut lines = String::new();
+    for (status, sessions) in snapshots {
+        let path = format!(
+            "{}.{}.{}",
+            prefix,
+            graphite_escape(server),
+            graphite_escape(&status.name)
+        );
+        for (name, value) in hub_fields(status) {
+            lines.push_str(&format!("{}.{} {} {}\n", path, name, value, timestamp));
+        }
+        for (user, fields) in user_fields(sessions) {
+            for (name, value) in fields {
+                lines.push_str(&format!(
+                    "{}.users.{}.{} {} {}\n",
+                    path,
+                    graphite_escape(user),
+                    name,
+                    value,
+                    timestamp
+                ));
+            }
+        }
+    }
+    lines
+}
+
+// A node of the path can't have dot, and whitespaces separate the value
+fn graphite_escape(s: &str) -> String {
+    s.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+// -------------------------------------------------------------------------------------------------
+// StatsD / DogStatsD
+// -------------------------------------------------------------------------------------------------
+
+// Transfer of each session in the last push, to send the deltas as counters.
+// Sessions are tracked by hub and session name, because the total of a user decreases when one of
+// the sessions is closed.
+// The first push of each hub only records the transfer, because the deltas since the last push are unknown.
+// Hubs which are not in the push ( e.g. the collection failed ) keep the transfer of the last push,
+// so that the lifetime transfer is not sent as a delta when they are back.
+#[derive(Default)]
+pub struct StatsdState {
+    last: HashMap<String, HashMap<String, (f64, f64)>>,
+}
+
+// Hub fields and user sessions are sent as gauges, and user transfer as counters.
+// DogStatsD has tags of server, hub and user. In plain StatsD, they are embedded in the name as Graphite.
+pub fn statsd(
+    snapshots: &[Snapshot],
+    prefix: &str,
+    server: &str,
+    dogstatsd: bool,
+    state: &mut StatsdState,
+) -> Vec<String> {
+    let mut lines = Vec::new();
+    for (status, sessions) in snapshots {
+        let name = |kind: &str, user: Option<&str>, field: &str| {
+            if dogstatsd {
+                let mut tags = format!(
+                    "server:{},hub:{}",
+                    statsd_escape(server),
+                    statsd_escape(&status.name)
+                );
+                if let Some(user) = user {
+                    tags.push_str(&format!(",user:{}", statsd_escape(user)));
+                }
+                (
+                    format!("{}.{}.{}", prefix, kind, field),
+                    format!("|#{}", tags),
+                )
+            } else {
+                let mut name = format!(
+                    "{}.{}.{}",
+                    prefix,
+                    graphite_escape(server),
+                    graphite_escape(&status.name)
+                );
+                if let Some(user) = user {
+                    name.push_str(&format!(".users.{}", graphite_escape(user)));
+                }
+                (format!("{}.{}", name, field), String::new())
+            }
+        };
+
+        // New sessions are counted from 0, and closed sessions are not counted any more
+        let mut deltas: HashMap<&str, (f64, f64)> = HashMap::new();
+        let mut current = HashMap::new();
+        for session in sessions {
+            let transfer = (session.transfer_bytes, session.transfer_packets);
+            if let Some(last) = state.last.get(&status.name) {
+                let delta = |current: f64, last: Option<f64>| match last {
+                    Some(last) if current >= last => current - last,
+                    _ => current,
+                };
+                let last = last.get(&session.name);
+                let user = deltas.entry(&session.user).or_insert((0.0, 0.0));
+                user.0 += delta(transfer.0, last.map(|x| x.0));
+                user.1 += delta(transfer.1, last.map(|x| x.1));
+            }
+            current.insert(session.name.clone(), transfer);
+        }
+        state.last.insert(status.name.clone(), current);
+
+        for (field, value) in hub_fields(status) {
+            let (name, tags) = name("hub", None, field);
+            lines.push(format!("{}:{}|g{}", name, value, tags));
+        }
+        for (user, fields) in user_fields(sessions) {
+            let (name_sessions, tags) = name("user", Some(user), "sessions");
+            lines.push(format!("{}:{}|g{}", name_sessions, fields[0].1, tags));
+
+            if let Some((bytes, packets)) = deltas.get(user) {
+                let (name_bytes, _) = name("user", Some(user), "transfer_bytes");
+                let (name_packets, _) = name("user", Some(user), "transfer_packets");
+                lines.push(format!("{}:{}|c{}", name_bytes, bytes, tags));
+                lines.push(format!("{}:{}|c{}", name_packets, packets, tags));
+            }
+        }
+    }
+    lines
+}
+
+// Comma, pipe and hash separate tags and fields of DogStatsD
+fn statsd_escape(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            ',' | '|' | '#' | ' ' | '\n' => '_',
+            _ => c,
+        })
+        .collect()
+}
+
+// -------------------------------------------------------------------------------------------------
+// Test
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::softether_reader::{HubSession, HubType};
+
+    fn snapshots() -> Vec<Snapshot> {
+        let mut status = HubStatus::new();
+        status.name = String::from("HUB1");
+        status.online = true;
+        status.hub_type = HubType::Standalone;
+        status.sessions = 3.0;
+        status.sessions_client = 3.0;
+        status.users = 2.0;
+        status.mac_tables = 134.0;
+        status.ip_tables = 211.0;
+        status.logins = 18965.0;
+        status.outgoing_unicast_packets = 7262679895.0;
+        status.outgoing_unicast_bytes = 4153388417848.0;
+        status.incoming_unicast_packets = 8840585104.0;
+        status.incoming_unicast_bytes = 4676951155757.0;
+
+        let sessions = vec![
+            HubSession::new("SID-USER1-1", "user1", 1000.0, 10.0),
+            HubSession::new("SID-USER 2,X-2", "user 2,x", 500.5, 5.0),
+            HubSession::new("SID-USER1-3", "user1", 24.0, 2.0),
+        ];
+        vec![(status, sessions)]
+    }
+
+    #[test]
+    fn test_statsd() {
+        let mut state = StatsdState::default();
+        let ret = statsd(&snapshots(), "softether", "vpn:8888", true, &mut state);
+        // Counters are not sent at the first push
+        assert_eq!(ret.len(), 21);
+        assert_eq!(ret[0], "softether.hub.online:1|g|#server:vpn:8888,hub:HUB1");
+        assert_eq!(
+            ret[19],
+            "softether.user.sessions:1|g|#server:vpn:8888,hub:HUB1,user:user_2_x"
+        );
+
+        let mut snapshots = snapshots();
+        snapshots[0].1[0].transfer_bytes += 100.0;
+        snapshots[0].1[0].transfer_packets += 1.0;
+        // The session of "user 2,x" is closed, and a new session is opened
+        snapshots[0].1[1].name = String::from("SID-USER 2,X-4");
+        snapshots[0].1[1].transfer_bytes = 10.0;
+        snapshots[0].1[1].transfer_packets = 1.0;
+        let ret = statsd(&snapshots, "softether", "vpn:8888", true, &mut state);
+        let expected = [
+            "softether.user.sessions:1|g|#server:vpn:8888,hub:HUB1,user:user_2_x",
+            "softether.user.transfer_bytes:10|c|#server:vpn:8888,hub:HUB1,user:user_2_x",
+            "softether.user.transfer_packets:1|c|#server:vpn:8888,hub:HUB1,user:user_2_x",
+            "softether.user.sessions:2|g|#server:vpn:8888,hub:HUB1,user:user1",
+            "softether.user.transfer_bytes:100|c|#server:vpn:8888,hub:HUB1,user:user1",
+            "softether.user.transfer_packets:1|c|#server:vpn:8888,hub:HUB1,user:user1",
+        ];
+        assert_eq!(ret[19..], expected[..]);
+
+        let ret = statsd(&snapshots, "softether", "vpn:8888", false, &mut state);
+        assert_eq!(ret[2], "softether.vpn_8888.HUB1.sessions:3|g");
+        assert_eq!(
+            ret[23],
+            "softether.vpn_8888.HUB1.users.user1.transfer_bytes:0|c"
+        );
+    }
+
+    #[test]
+    fn test_statsd_closed_session() {
+        let mut state = StatsdState::default();
+        let mut snapshots = snapshots();
+        statsd(&snapshots, "softether", "vpn", true, &mut state);
+
+        // One of the 2 sessions of user1 is closed, and the other one transfers 50 bytes
+        snapshots[0].1.remove(0);
+        snapshots[0].1[1].transfer_bytes += 50.0;
+        snapshots[0].1[1].transfer_packets += 1.0;
+        let ret = statsd(&snapshots, "softether", "vpn", true, &mut state);
+        assert_eq!(
+            ret[ret.len() - 3..],
+            [
+                "softether.user.sessions:1|g|#server:vpn,hub:HUB1,user:user1",
+                "softether.user.transfer_bytes:50|c|#server:vpn,hub:HUB1,user:user1",
+                "softether.user.transfer_packets:1|c|#server:vpn,hub:HUB1,user:user1",
+            ]
+        );
+
+        // The closed session is not counted again when the user is gone
+        snapshots[0].1.remove(1);
+        let ret = statsd(&snapshots, "softether", "vpn", true, &mut state);
+        assert!(ret.iter().all(|x| !x.contains("user:user1")));
+    }
+
+    #[test]
+    fn test_statsd_failed_collection() {
+        let mut state = StatsdState::default();
+        let mut snapshots = snapshots();
+        statsd(&snapshots, "softether", "vpn", true, &mut state);
+
+        // HUB1 is not pushed because the collection failed, and HUB2 is pushed for the first time
+        let mut status = HubStatus::new();
+        status.name = String::from("HUB2");
+        let hub2 = vec![(
+            status,
+            vec![HubSession::new("SID-USER3-1", "user3", 300.0, 3.0)],
+        )];
+        let ret = statsd(&hub2, "softether", "vpn", true, &mut state);
+        assert!(ret.iter().all(|x| !x.contains("|c|")));
+
+        // Only the transfer since the last push of HUB1 is sent
+        snapshots[0].1[0].transfer_bytes += 100.0;
+        snapshots[0].1[0].transfer_packets += 1.0;
+        let ret = statsd(&snapshots, "softether", "vpn", true, &mut state);
+        assert_eq!(
+            ret[ret.len() - 2..],
+            [
+                "softether.user.transfer_bytes:100|c|#server:vpn,hub:HUB1,user:user1",
+                "softether.user.transfer_packets:1|c|#server:vpn,hub:HUB1,user:user1",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_influxdb() {
+        let ret = influxdb(&snapshots(), "vpn.example.com:8888", 1600000000000000000);
+        let expected = r#"softether_hub,server=vpn.example.com:8888,hub=HUB1,type=standalone online=1,secure_nat=0,sessions=3,sessions_client=3,sessions_bridge=0,access_lists=0,users=2,groups=0,mac_tables=134,ip_tables=211,logins=18965,outgoing_unicast_packets=7262679895,outgoing_unicast_bytes=4153388417848,outgoing_broadcast_packets=0,outgoing_broadcast_bytes=0,incoming_unicast_packets=8840585104,incoming_unicast_bytes=4676951155757,incoming_broadcast_packets=0,incoming_broadcast_bytes=0 1600000000000000000
+softether_user,server=vpn.example.com:8888,hub=HUB1,user=user\ 2\,x sessions=1,transfer_bytes=500.5,transfer_packets=5 1600000000000000000
+softether_user,server=vpn.example.com:8888,hub=HUB1,user=user1 sessions=2,transfer_bytes=1024,transfer_packets=12 1600000000000000000
+"#;
+        assert_eq!(ret, expected);
+    }
+
+    #[test]
+    fn test_graphite() {
+        let ret = graphite(
+            &snapshots(),
+            "softether",
+            "vpn.example.com:8888",
+            1600000000,
+        );
+        let expected = r#"softether.vpn_example_com_8888.HUB1.online 1 1600000000
+softether.vpn_example_com_8888.HUB1.secure_nat 0 1600000000
+softether.vpn_example_com_8888.HUB1.sessions 3 1600000000
+softether.vpn_example_com_8888.HUB1.sessions_client 3 1600000000
+softether.vpn_example_com_8888.HUB1.sessions_bridge 0 1600000000
+softether.vpn_example_com_8888.HUB1.access_lists 0 1600000000
+softether.vpn_example_com_8888.HUB1.users 2 1600000000
+softether.vpn_example_com_8888.HUB1.groups 0 1600000000
+softether.vpn_example_com_8888.HUB1.mac_tables 134 1600000000
+softether.vpn_example_com_8888.HUB1.ip_tables 211 1600000000
+softether.vpn_example_com_8888.HUB1.logins 18965 1600000000
+softether.vpn_example_com_8888.HUB1.outgoing_unicast_packets 7262679895 1600000000
+softether.vpn_example_com_8888.HUB1.outgoing_unicast_bytes 4153388417848 1600000000
+softether.vpn_example_com_8888.HUB1.outgoing_broadcast_packets 0 1600000000
+softether.vpn_example_com_8888.HUB1.outgoing_broadcast_bytes 0 1600000000
+softether.vpn_example_com_8888.HUB1.incoming_unicast_packets 8840585104 1600000000
+softether.vpn_example_com_8888.HUB1.incoming_unicast_bytes 4676951155757 1600000000
+softether.vpn_example_com_8888.HUB1.incoming_broadcast_packets 0 1600000000
+softether.vpn_example_com_8888.HUB1.incoming_broadcast_bytes 0 1600000000
+softether.vpn_example_com_8888.HUB1.users.user_2_x.sessions 1 1600000000
+softether.vpn_example_com_8888.HUB1.users.user_2_x.transfer_bytes 500.5 1600000000
+softether.vpn_example_com_8888.HUB1.users.user_2_x.transfer_packets 5 1600000000
+softether.vpn_example_com_8888.HUB1.users.user1.sessions 2 1600000000
+softether.vpn_example_com_8888.HUB1.users.user1.transfer_bytes 1024 1600000000
+softether.vpn_example_com_8888.HUB1.users.user1.transfer_packets 12 1600000000
+"#;
+        assert_eq!(ret, expected);
+    }
+}
@@ -1,26 +1,137 @@
-use csv;
+use crate::strtable;
+use chrono::NaiveDateTime;
+use lazy_static::lazy_static;
+use log::{debug, log_enabled, warn, Level};
+use prometheus::{register_histogram_vec, register_int_counter_vec, HistogramVec, IntCounterVec};
+use serde::{Serialize, Serializer};
+use std::env;
 use std::error::Error;
 use std::fmt;
-use std::io::Write;
+use std::io::{self, Write};
+use std::num::{ParseFloatError, ParseIntError};
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
+use std::time::Instant;
 
 #[derive(Debug)]
-pub struct SoftEtherError {
-    msg: String,
+pub enum SoftEtherError {
+    /// vpncmd binary can't be executed
+    VpncmdMissing(String),
+    /// VPN server can't be connected
+    Unreachable(i32, String),
+    /// Password is incorrect
+    AuthFailed(i32, String),
+    /// Virtual hub doesn't exist
+    HubNotFound(i32, String),
+    /// Administrator privilege is not enough for the command
+    PermissionDenied(i32, String),
+    /// Other errors reported by vpncmd
+    Vpncmd(i32, String),
+    Io(io::Error),
+    Parse(String),
+}
+
+impl SoftEtherError {
+    // Error codes are defined in src/Cedar/Cedar.h of SoftEther VPN
+    fn from_code(code: i32, msg: String) -> SoftEtherError {
+        match code {
+            1..=4 => SoftEtherError::Unreachable(code, msg),
+            8 => SoftEtherError::HubNotFound(code, msg),
+            9 => SoftEtherError::AuthFailed(code, msg),
+            52 => SoftEtherError::PermissionDenied(code, msg),
+            _ => SoftEtherError::Vpncmd(code, msg),
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SoftEtherError::VpncmdMissing(_) => "vpncmd_missing",
+            SoftEtherError::Unreachable(_, _) => "unreachable",
+            SoftEtherError::AuthFailed(_, _) => "auth_failed",
+            SoftEtherError::HubNotFound(_, _) => "hub_not_found",
+            SoftEtherError::PermissionDenied(_, _) => "permission_denied",
+            SoftEtherError::Vpncmd(_, _) => "vpncmd",
+            SoftEtherError::Io(_) => "io",
+            SoftEtherError::Parse(_) => "parse",
+        }
+    }
 }
 
 impl fmt::Display for SoftEtherError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.msg)
+        match self {
+            SoftEtherError::VpncmdMissing(x) => write!(f, "vpncmd can't be executed ( {} )", x),
+            SoftEtherError::Unreachable(code, x)
+            | SoftEtherError::AuthFailed(code, x)
+            | SoftEtherError::HubNotFound(code, x)
+            | SoftEtherError::PermissionDenied(code, x)
+            | SoftEtherError::Vpncmd(code, x) => {
+                write!(f, "vpncmd failed with error code {} ( {} )", code, x)
+            }
+            SoftEtherError::Io(x) => write!(f, "{}", x),
+            SoftEtherError::Parse(x) => write!(f, "vpncmd output parse failed ( {} )", x),
+        }
+    }
+}
+
+impl Error for SoftEtherError {}
+
+impl From<io::Error> for SoftEtherError {
+    fn from(x: io::Error) -> SoftEtherError {
+        SoftEtherError::Io(x)
     }
 }
 
-impl Error for SoftEtherError {
-    fn description(&self) -> &str {
-        &self.msg
+impl From<csv::Error> for SoftEtherError {
+    fn from(x: csv::Error) -> SoftEtherError {
+        SoftEtherError::Parse(x.to_string())
     }
 }
 
+impl From<ParseFloatError> for SoftEtherError {
+    fn from(x: ParseFloatError) -> SoftEtherError {
+        SoftEtherError::Parse(x.to_string())
+    }
+}
+
+impl From<ParseIntError> for SoftEtherError {
+    fn from(x: ParseIntError) -> SoftEtherError {
+        SoftEtherError::Parse(x.to_string())
+    }
+}
+
+impl From<chrono::ParseError> for SoftEtherError {
+    fn from(x: chrono::ParseError) -> SoftEtherError {
+        SoftEtherError::Parse(x.to_string())
+    }
+}
+
+lazy_static! {
+    static ref SCRAPE_DURATION: HistogramVec = register_histogram_vec!(
+        "softether_scrape_duration_seconds",
+        "Duration of vpncmd execution in seconds.",
+        &["hub", "command"]
+    )
+    .unwrap();
+    static ref VPNCMD_EXEC: IntCounterVec = register_int_counter_vec!(
+        "softether_vpncmd_exec_total",
+        "Number of vpncmd executions by result.",
+        &["command", "result"]
+    )
+    .unwrap();
+}
+
+// Commands run in hub admin mode
+static HUB_COMMANDS: &[&str] = &[
+    "StatusGet",
+    "SessionList",
+    "OptionsGet",
+    "CAList",
+    "CrlList",
+    "RadiusServerGet",
+    "LogGet",
+];
+
 pub struct SoftEtherReader;
 
 impl SoftEtherReader {
@@ -29,54 +140,172 @@ impl SoftEtherReader {
         server: &str,
         hub: &str,
         password: &str,
-    ) -> Result<HubStatus, Box<dyn Error>> {
-        let mut child = Command::new(vpncmd)
-            .arg(server)
-            .arg("/SERVER")
-            .arg(format!("/HUB:{}", hub))
-            .arg(format!("/PASSWORD:{}", password))
-            .arg("/CSV")
-            .arg("/CMD")
-            .arg("StatusGet")
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .spawn()?;
+    ) -> Result<HubStatus, SoftEtherError> {
+        let output = SoftEtherReader::exec(vpncmd, server, Some(hub), password, "StatusGet")?;
+        SoftEtherReader::decode_hub_status(&output)
+    }
 
-        {
-            let stdin = child.stdin.as_mut().unwrap();
-            // Input Ctrl-D to interrupt password prompt
-            stdin.write_all(&[4])?;
+    pub fn hub_sessions(
+        vpncmd: &str,
+        server: &str,
+        hub: &str,
+        password: &str,
+    ) -> Result<Vec<HubSession>, SoftEtherError> {
+        let output = SoftEtherReader::exec(vpncmd, server, Some(hub), password, "SessionList")?;
+        SoftEtherReader::decode_hub_sessions(&output)
+    }
+
+    pub fn openvpn_status(
+        vpncmd: &str,
+        server: &str,
+        password: &str,
+    ) -> Result<bool, SoftEtherError> {
+        let output = SoftEtherReader::exec(vpncmd, server, None, password, "OpenVpnGet")?;
+        SoftEtherReader::decode_openvpn_status(&output)
+    }
+
+    pub fn sstp_status(vpncmd: &str, server: &str, password: &str) -> Result<bool, SoftEtherError> {
+        let output = SoftEtherReader::exec(vpncmd, server, None, password, "SstpGet")?;
+        SoftEtherReader::decode_sstp_status(&output)
+    }
+
+    pub fn ipsec_status(
+        vpncmd: &str,
+        server: &str,
+        password: &str,
+    ) -> Result<IpsecStatus, SoftEtherError> {
+        let output = SoftEtherReader::exec(vpncmd, server, None, password, "IPsecGet")?;
+        SoftEtherReader::decode_ipsec_status(&output)
+    }
+
+    pub fn etherip_clients(
+        vpncmd: &str,
+        server: &str,
+        password: &str,
+    ) -> Result<Vec<EtherIpClient>, SoftEtherError> {
+        let output = SoftEtherReader::exec(vpncmd, server, None, password, "EtherIpClientList")?;
+        SoftEtherReader::decode_etherip_clients(&output)
+    }
+
+    pub fn hub_config(
+        vpncmd: &str,
+        server: &str,
+        hub: &str,
+        password: &str,
+    ) -> Result<HubConfig, SoftEtherError> {
+        let output = SoftEtherReader::exec(vpncmd, server, Some(hub), password, "OptionsGet")?;
+        let mut config = SoftEtherReader::decode_hub_options(&output)?;
+
+        let output = SoftEtherReader::exec(vpncmd, server, Some(hub), password, "CAList")?;
+        config.trusted_cas = SoftEtherReader::decode_count(&output)?;
+
+        let output = SoftEtherReader::exec(vpncmd, server, Some(hub), password, "CrlList")?;
+        config.crls = SoftEtherReader::decode_count(&output)?;
+
+        let output = SoftEtherReader::exec(vpncmd, server, Some(hub), password, "RadiusServerGet")?;
+        config.radius = SoftEtherReader::decode_hub_radius(&output)?;
+
+        let output = SoftEtherReader::exec(vpncmd, server, Some(hub), password, "LogGet")?;
+        let (security_log, packet_log) = SoftEtherReader::decode_hub_log(&output)?;
+        config.security_log = security_log;
+        config.packet_log = packet_log;
+
+        Ok(config)
+    }
+
+    pub fn ddns_status(
+        vpncmd: &str,
+        server: &str,
+        password: &str,
+    ) -> Result<DdnsStatus, SoftEtherError> {
+        let output = SoftEtherReader::exec(vpncmd, server, None, password, "DDnsGetStatus")?;
+        SoftEtherReader::decode_ddns_status(&output)
+    }
+
+    pub fn azure_status(
+        vpncmd: &str,
+        server: &str,
+        password: &str,
+    ) -> Result<AzureStatus, SoftEtherError> {
+        let output = SoftEtherReader::exec(vpncmd, server, None, password, "VpnAzureGetStatus")?;
+        SoftEtherReader::decode_azure_status(&output)
+    }
+
+    // Find the vpncmd binary in the same way as Command::new ( path or PATH lookup )
+    pub fn resolve_vpncmd(vpncmd: &str) -> Option<PathBuf> {
+        let path = Path::new(vpncmd);
+        if path.components().count() > 1 {
+            return if path.is_file() {
+                Some(path.to_path_buf())
+            } else {
+                None
+            };
         }
+        env::var_os("PATH").and_then(|paths| {
+            env::split_paths(&paths)
+                .flat_map(|dir| vec![dir.join(vpncmd), dir.join(format!("{}.exe", vpncmd))])
+                .find(|x| x.is_file())
+        })
+    }
 
-        let output = child.wait_with_output()?;
+    pub fn register() {
+        lazy_static::initialize(&SCRAPE_DURATION);
+        lazy_static::initialize(&VPNCMD_EXEC);
+    }
 
-        if !output.status.success() {
-            let msg = String::from_utf8_lossy(output.stdout.as_slice());
-            return Err(Box::new(SoftEtherError {
-                msg: String::from(format!("vpncmd failed ( {} )", msg)),
-            }));
+    // Remove the series of a hub which is removed from the config
+    pub fn remove_hub(hub: &str) {
+        for cmd in HUB_COMMANDS {
+            let _ = SCRAPE_DURATION.remove_label_values(&[hub, cmd]);
         }
+    }
+
+    // Run a vpncmd command in server admin mode ( hub is None ) or hub admin mode
+    fn exec(
+        vpncmd: &str,
+        server: &str,
+        hub: Option<&str>,
+        password: &str,
+        cmd: &str,
+    ) -> Result<Vec<u8>, SoftEtherError> {
+        let timer = SCRAPE_DURATION
+            .with_label_values(&[hub.unwrap_or(""), cmd])
+            .start_timer();
+        let ret = SoftEtherReader::exec_vpncmd(vpncmd, server, hub, password, cmd);
+        timer.observe_duration();
+
+        let result = match &ret {
+            Ok(_) => "success",
+            Err(x) => x.kind(),
+        };
+        VPNCMD_EXEC.with_label_values(&[cmd, result]).inc();
 
-        SoftEtherReader::decode_hub_status(&output.stdout)
+        ret
     }
 
-    pub fn hub_sessions(
+    fn exec_vpncmd(
         vpncmd: &str,
         server: &str,
-        hub: &str,
+        hub: Option<&str>,
         password: &str,
-    ) -> Result<Vec<HubSession>, Box<dyn Error>> {
+        cmd: &str,
+    ) -> Result<Vec<u8>, SoftEtherError> {
+        let mut args = vec![String::from(server), String::from("/SERVER")];
+        if let Some(hub) = hub {
+            args.push(format!("/HUB:{}", hub));
+        }
+        args.push(format!("/PASSWORD:{}", password));
+        args.push(String::from("/CSV"));
+        args.push(String::from("/CMD"));
+        args.push(String::from(cmd));
+
+        let start = Instant::now();
         let mut child = Command::new(vpncmd)
-            .arg(server)
-            .arg("/SERVER")
-            .arg(format!("/HUB:{}", hub))
-            .arg(format!("/PASSWORD:{}", password))
-            .arg("/CSV")
-            .arg("/CMD")
-            .arg("SessionList")
+            .args(&args)
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
-            .spawn()?;
+            .spawn()
+            .map_err(|x| SoftEtherError::VpncmdMissing(format!("{}: {}", vpncmd, x)))?;
 
         {
             let stdin = child.stdin.as_mut().unwrap();
@@ -86,17 +315,47 @@ impl SoftEtherReader {
 
         let output = child.wait_with_output()?;
 
+        if log_enabled!(Level::Debug) {
+            debug!(
+                "vpncmd executed: {} ( exit code: {}, duration: {:.3}s )",
+                SoftEtherReader::command_line(vpncmd, &args),
+                output
+                    .status
+                    .code()
+                    .map(|x| x.to_string())
+                    .unwrap_or_else(|| String::from("none")),
+                start.elapsed().as_secs_f64()
+            );
+        }
+
+        // vpncmd exits with the error code of the command
         if !output.status.success() {
             let msg = String::from_utf8_lossy(output.stdout.as_slice());
-            return Err(Box::new(SoftEtherError {
-                msg: String::from(format!("vpncmd failed ( {} )", msg)),
-            }));
+            let msg = String::from(msg.trim());
+            return Err(match output.status.code() {
+                Some(code) => SoftEtherError::from_code(code, msg),
+                None => SoftEtherError::Vpncmd(-1, msg),
+            });
         }
 
-        SoftEtherReader::decode_hub_sessions(&output.stdout)
+        Ok(output.stdout)
+    }
+
+    // Command line for logging, in which the password is redacted
+    fn command_line(vpncmd: &str, args: &[String]) -> String {
+        let mut line = String::from(vpncmd);
+        for arg in args {
+            line.push(' ');
+            if arg.starts_with("/PASSWORD:") {
+                line.push_str("/PASSWORD:********");
+            } else {
+                line.push_str(arg);
+            }
+        }
+        line
     }
 
-    fn decode_hub_status(src: &[u8]) -> Result<HubStatus, Box<dyn Error>> {
+    fn decode_hub_status(src: &[u8]) -> Result<HubStatus, SoftEtherError> {
         let mut rdr = csv::Reader::from_reader(src);
         let mut status = HubStatus::new();
 
@@ -104,125 +363,58 @@ impl SoftEtherReader {
             let entry = entry?;
             let key = entry.get(0).unwrap_or("");
             let val = entry.get(1).unwrap_or("");
-            match key.as_ref() {
-                "仮想 HUB 名" => status.name = String::from(val),
-                "状態" => {
-                    status.online = if val == "オンライン" {
-                        true
-                    } else {
-                        false
-                    }
-                }
-                "SecureNAT 機能" => {
-                    status.secure_nat = if val == "無効" { false } else { true }
-                }
-                "セッション数" => status.sessions = val.parse()?,
-                "セッション数 (クライアント)" => {
-                    status.sessions_client = val.parse()?
-                }
-                "セッション数 (ブリッジ)" => status.sessions_bridge = val.parse()?,
-                "アクセスリスト数" => status.access_lists = val.parse()?,
-                "ユーザー数" => status.users = val.parse()?,
-                "グループ数" => status.groups = val.parse()?,
-                "MAC テーブル数" => status.mac_tables = val.parse()?,
-                "IP テーブル数" => status.ip_tables = val.parse()?,
-                "ログイン回数" => status.logins = val.parse()?,
-                "送信ユニキャストパケット数" => {
-                    status.outgoing_unicast_packets = SoftEtherReader::decode_packets(val)?
-                }
-                "送信ユニキャスト合計サイズ" => {
-                    status.outgoing_unicast_bytes = SoftEtherReader::decode_bytes(val)?
-                }
-                "送信ブロードキャストパケット数" => {
-                    status.outgoing_broadcast_packets = SoftEtherReader::decode_packets(val)?
-                }
-                "送信ブロードキャスト合計サイズ" => {
-                    status.outgoing_broadcast_bytes = SoftEtherReader::decode_bytes(val)?
-                }
-                "受信ユニキャストパケット数" => {
-                    status.incoming_unicast_packets = SoftEtherReader::decode_packets(val)?
-                }
-                "受信ユニキャスト合計サイズ" => {
-                    status.incoming_unicast_bytes = SoftEtherReader::decode_bytes(val)?
-                }
-                "受信ブロードキャストパケット数" => {
-                    status.incoming_broadcast_packets = SoftEtherReader::decode_packets(val)?
-                }
-                "受信ブロードキャスト合計サイズ" => {
-                    status.incoming_broadcast_bytes = SoftEtherReader::decode_bytes(val)?
-                }
-                "Virtual Hub Name" => status.name = String::from(val),
-                "Status" => status.online = if val == "Online" { true } else { false },
-                "SecureNAT" => status.secure_nat = if val == "Disabled" { false } else { true },
-                "Sessions" => status.sessions = val.parse()?,
-                "Sessions (Client)" => status.sessions_client = val.parse()?,
-                "Sessions (Bridge)" => status.sessions_bridge = val.parse()?,
-                "Access Lists" => status.access_lists = val.parse()?,
-                "Users" => status.users = val.parse()?,
-                "Groups" => status.groups = val.parse()?,
-                "MAC Tables" => status.mac_tables = val.parse()?,
-                "IP Tables" => status.ip_tables = val.parse()?,
-                "Num Logins" => status.logins = val.parse()?,
-                "Outgoing Unicast Packets" => {
-                    status.outgoing_unicast_packets = SoftEtherReader::decode_packets(val)?
-                }
-                "Outgoing Unicast Total Size" => {
-                    status.outgoing_unicast_bytes = SoftEtherReader::decode_bytes(val)?
-                }
-                "Outgoing Broadcast Packets" => {
-                    status.outgoing_broadcast_packets = SoftEtherReader::decode_packets(val)?
+            let id = strtable::lookup(key);
+            match id {
+                Some(x) if HUB_STATUS_FIELDS.contains(&x) => status.report.seen(x),
+                _ => status.report.unknown.push(String::from(key)),
+            }
+            match id {
+                Some("SM_HUB_STATUS_HUBNAME") => status.name = String::from(val),
+                Some("SM_HUB_STATUS_ONLINE") => status.online = strtable::is(val, "SM_HUB_ONLINE"),
+                Some("SM_HUB_TYPE") => status.hub_type = HubType::from_str(val),
+                Some("SM_HUB_SECURE_NAT") => {
+                    status.secure_nat = !strtable::is(val, "SM_HUB_SECURE_NAT_NO")
                 }
-                "Outgoing Broadcast Total Size" => {
-                    status.outgoing_broadcast_bytes = SoftEtherReader::decode_bytes(val)?
+                Some("SM_HUB_NUM_SESSIONS") => status.sessions = val.parse()?,
+                Some("SM_HUB_NUM_SESSIONS_CLIENT") => status.sessions_client = val.parse()?,
+                Some("SM_HUB_NUM_SESSIONS_BRIDGE") => status.sessions_bridge = val.parse()?,
+                Some("SM_HUB_NUM_ACCESSES") => status.access_lists = val.parse()?,
+                Some("SM_HUB_NUM_USERS") => status.users = val.parse()?,
+                Some("SM_HUB_NUM_GROUPS") => status.groups = val.parse()?,
+                Some("SM_HUB_NUM_MAC_TABLES") => status.mac_tables = val.parse()?,
+                Some("SM_HUB_NUM_IP_TABLES") => status.ip_tables = val.parse()?,
+                Some("SM_HUB_NUM_LOGIN") => status.logins = val.parse()?,
+                Some("SM_HUB_LAST_LOGIN_TIME") => {
+                    status.last_login = SoftEtherReader::decode_datetime(val)
                 }
-                "Incoming Unicast Packets" => {
-                    status.incoming_unicast_packets = SoftEtherReader::decode_packets(val)?
+                Some("SM_HUB_LAST_COMM_TIME") => {
+                    status.last_communication = SoftEtherReader::decode_datetime(val)
                 }
-                "Incoming Unicast Total Size" => {
-                    status.incoming_unicast_bytes = SoftEtherReader::decode_bytes(val)?
+                Some("SM_HUB_CREATED_TIME") => {
+                    status.created = SoftEtherReader::decode_datetime(val)
                 }
-                "Incoming Broadcast Packets" => {
-                    status.incoming_broadcast_packets = SoftEtherReader::decode_packets(val)?
-                }
-                "Incoming Broadcast Total Size" => {
-                    status.incoming_broadcast_bytes = SoftEtherReader::decode_bytes(val)?
-                }
-                "虚拟 HUB 名称" => status.name = String::from(val),
-                "状态" => status.online = if val == "在线" { true } else { false },
-                "SecureNAT 机能" => {
-                    status.secure_nat = if val == "无效" { false } else { true }
-                }
-                "会话数" => status.sessions = val.parse()?,
-                "会话数 (客户端)" => status.sessions_client = val.parse()?,
-                "会话数 (网桥)" => status.sessions_bridge = val.parse()?,
-                "访问列表" => status.access_lists = val.parse()?,
-                "用户数" => status.users = val.parse()?,
-                "组数" => status.groups = val.parse()?,
-                "MAC 表数" => status.mac_tables = val.parse()?,
-                "IP 表数" => status.ip_tables = val.parse()?,
-                "登录次数" => status.logins = val.parse()?,
-                "发送单播数据包" => {
+                Some("SM_ST_SEND_UCAST_NUM") => {
                     status.outgoing_unicast_packets = SoftEtherReader::decode_packets(val)?
                 }
-                "发送单播总量" => {
+                Some("SM_ST_SEND_UCAST_SIZE") => {
                     status.outgoing_unicast_bytes = SoftEtherReader::decode_bytes(val)?
                 }
-                "发送广播数据包" => {
+                Some("SM_ST_SEND_BCAST_NUM") => {
                     status.outgoing_broadcast_packets = SoftEtherReader::decode_packets(val)?
                 }
-                "发送广播总量" => {
+                Some("SM_ST_SEND_BCAST_SIZE") => {
                     status.outgoing_broadcast_bytes = SoftEtherReader::decode_bytes(val)?
                 }
-                "接收单播数据包" => {
+                Some("SM_ST_RECV_UCAST_NUM") => {
                     status.incoming_unicast_packets = SoftEtherReader::decode_packets(val)?
                 }
-                "接收单播总量" => {
+                Some("SM_ST_RECV_UCAST_SIZE") => {
                     status.incoming_unicast_bytes = SoftEtherReader::decode_bytes(val)?
                 }
-                "接收广播数据包" => {
+                Some("SM_ST_RECV_BCAST_NUM") => {
                     status.incoming_broadcast_packets = SoftEtherReader::decode_packets(val)?
                 }
-                "接收广播总量" => {
+                Some("SM_ST_RECV_BCAST_SIZE") => {
                     status.incoming_broadcast_bytes = SoftEtherReader::decode_bytes(val)?
                 }
                 _ => (),
@@ -231,7 +423,7 @@ impl SoftEtherReader {
         Ok(status)
     }
 
-    fn decode_hub_sessions(src: &[u8]) -> Result<Vec<HubSession>, Box<dyn Error>> {
+    fn decode_hub_sessions(src: &[u8]) -> Result<Vec<HubSession>, SoftEtherError> {
         let mut rdr = csv::Reader::from_reader(src);
         let mut sessions = Vec::new();
 
@@ -246,13 +438,15 @@ impl SoftEtherReader {
             let transfer_bytes = entry.get(6).unwrap_or("");
             let transfer_packets = entry.get(7).unwrap_or("");
 
-            let connections = SoftEtherReader::decode_connections(connections)?;
+            let vlan_id = SoftEtherReader::decode_vlan_id(vlan_id)?;
+            let connections = SoftEtherReader::decode_connections(connections);
             let transfer_bytes = SoftEtherReader::decode_bytes(transfer_bytes)?;
-            let transfer_packets = SoftEtherReader::decode_bytes(transfer_packets)?;
+            let transfer_packets = SoftEtherReader::decode_packets(transfer_packets)?;
 
             let session = HubSession {
+                protocol: SessionProtocol::from_session_name(name),
                 name: String::from(name),
-                vlan_id: String::from(vlan_id),
+                vlan_id,
                 location: String::from(location),
                 user: String::from(user),
                 source: String::from(source),
@@ -267,42 +461,295 @@ impl SoftEtherReader {
         Ok(sessions)
     }
 
-    fn decode_packets(src: &str) -> Result<f64, Box<dyn Error>> {
-        let ret = String::from(src)
-            .replace(",", "")
-            .replace(" パケット", "")
-            .replace(" packets", "")
-            .replace(" 数据包", "")
+    fn decode_openvpn_status(src: &[u8]) -> Result<bool, SoftEtherError> {
+        let mut rdr = csv::Reader::from_reader(src);
+        let mut enabled = false;
+
+        for entry in rdr.records() {
+            let entry = entry?;
+            let key = entry.get(0).unwrap_or("");
+            let val = entry.get(1).unwrap_or("");
+            if strtable::lookup(key) == Some("CMD_OpenVpnGet_PRINT_Enabled") {
+                enabled = SoftEtherReader::decode_bool(val);
+            }
+        }
+        Ok(enabled)
+    }
+
+    fn decode_sstp_status(src: &[u8]) -> Result<bool, SoftEtherError> {
+        let mut rdr = csv::Reader::from_reader(src);
+        let mut enabled = false;
+
+        for entry in rdr.records() {
+            let entry = entry?;
+            let key = entry.get(0).unwrap_or("");
+            let val = entry.get(1).unwrap_or("");
+            if strtable::lookup(key) == Some("CMD_SstpGet_PRINT_Enabled") {
+                enabled = SoftEtherReader::decode_bool(val);
+            }
+        }
+        Ok(enabled)
+    }
+
+    fn decode_ipsec_status(src: &[u8]) -> Result<IpsecStatus, SoftEtherError> {
+        let mut rdr = csv::Reader::from_reader(src);
+        let mut status = IpsecStatus::new();
+
+        for entry in rdr.records() {
+            let entry = entry?;
+            let key = entry.get(0).unwrap_or("");
+            let val = entry.get(1).unwrap_or("");
+            // Every language has the parameter name of IPsecSet at the end of the item
+            if key.ends_with("(L2TP_IPSEC)") {
+                status.l2tp_ipsec = SoftEtherReader::decode_bool(val);
+            } else if key.ends_with("(L2TP_RAW)") {
+                status.l2tp_raw = SoftEtherReader::decode_bool(val);
+            } else if key.ends_with("(ETHERIP_IPSEC)") {
+                status.etherip_ipsec = SoftEtherReader::decode_bool(val);
+            }
+        }
+        Ok(status)
+    }
+
+    fn decode_etherip_clients(src: &[u8]) -> Result<Vec<EtherIpClient>, SoftEtherError> {
+        let mut rdr = csv::Reader::from_reader(src);
+        let mut clients = Vec::new();
+
+        for entry in rdr.records() {
+            let entry = entry?;
+            let id = entry.get(0).unwrap_or("");
+            let hub = entry.get(1).unwrap_or("");
+            let user = entry.get(2).unwrap_or("");
+
+            let client = EtherIpClient {
+                id: String::from(id),
+                hub: String::from(hub),
+                user: String::from(user),
+            };
+
+            clients.push(client);
+        }
+
+        Ok(clients)
+    }
+
+    fn decode_hub_options(src: &[u8]) -> Result<HubConfig, SoftEtherError> {
+        let mut rdr = csv::Reader::from_reader(src);
+        let mut config = HubConfig::new();
+
+        for entry in rdr.records() {
+            let entry = entry?;
+            let key = entry.get(0).unwrap_or("");
+            let val = entry.get(1).unwrap_or("");
+            if strtable::lookup(key) == Some("CMD_OptionsGet_ENUM") {
+                config.anonymous_enum = !SoftEtherReader::decode_bool(val);
+            }
+        }
+        Ok(config)
+    }
+
+    fn decode_hub_radius(src: &[u8]) -> Result<bool, SoftEtherError> {
+        let mut rdr = csv::Reader::from_reader(src);
+        let mut radius = false;
+
+        for entry in rdr.records() {
+            let entry = entry?;
+            let key = entry.get(0).unwrap_or("");
+            let val = entry.get(1).unwrap_or("");
+            if strtable::lookup(key) == Some("CMD_RadiusServerGet_STATUS") {
+                radius = SoftEtherReader::decode_bool(val);
+            }
+        }
+        Ok(radius)
+    }
+
+    fn decode_hub_log(src: &[u8]) -> Result<(bool, bool), SoftEtherError> {
+        let mut rdr = csv::Reader::from_reader(src);
+        let mut security_log = false;
+        let mut packet_log = false;
+
+        for entry in rdr.records() {
+            let entry = entry?;
+            let key = entry.get(0).unwrap_or("");
+            let val = entry.get(1).unwrap_or("");
+            match strtable::lookup(key) {
+                Some("CMD_Log_SecurityLog") => security_log = SoftEtherReader::decode_bool(val),
+                Some("CMD_Log_PacketLog") => packet_log = SoftEtherReader::decode_bool(val),
+                _ => (),
+            }
+        }
+        Ok((security_log, packet_log))
+    }
+
+    // Count the rows of list commands ( e.g. CAList, CrlList )
+    fn decode_count(src: &[u8]) -> Result<f64, SoftEtherError> {
+        let mut rdr = csv::Reader::from_reader(src);
+        let mut count = 0.0;
+
+        for entry in rdr.records() {
+            let _ = entry?;
+            count += 1.0;
+        }
+        Ok(count)
+    }
+
+    fn decode_ddns_status(src: &[u8]) -> Result<DdnsStatus, SoftEtherError> {
+        let mut rdr = csv::Reader::from_reader(src);
+        let mut status = DdnsStatus::new();
+
+        for entry in rdr.records() {
+            let entry = entry?;
+            let key = entry.get(0).unwrap_or("");
+            let val = entry.get(1).unwrap_or("");
+            match strtable::lookup(key) {
+                Some("CMD_DDnsGetStatus_FQDN") => status.hostname = String::from(val),
+                Some("CMD_DDnsGetStatus_IPv4") => status.ipv4 = String::from(val),
+                Some("CMD_DDnsGetStatus_IPv6") => status.ipv6 = String::from(val),
+                _ => (),
+            }
+        }
+        Ok(status)
+    }
+
+    fn decode_azure_status(src: &[u8]) -> Result<AzureStatus, SoftEtherError> {
+        let mut rdr = csv::Reader::from_reader(src);
+        let mut status = AzureStatus::new();
+
+        for entry in rdr.records() {
+            let entry = entry?;
+            let key = entry.get(0).unwrap_or("");
+            let val = entry.get(1).unwrap_or("");
+            match strtable::lookup(key) {
+                Some("CMD_VpnAzureGetStatus_PRINT_ENABLED") => {
+                    status.enabled = SoftEtherReader::decode_bool(val)
+                }
+                Some("CMD_VpnAzureGetStatus_PRINT_CONNECTED") => {
+                    status.connected = SoftEtherReader::decode_bool(val)
+                }
+                Some("CMD_VpnAzureGetStatus_PRINT_HOSTNAME") => status.hostname = String::from(val),
+                _ => (),
+            }
+        }
+        Ok(status)
+    }
+
+    fn decode_bool(src: &str) -> bool {
+        strtable::is(src, "SEC_YES")
+    }
+
+    fn decode_packets(src: &str) -> Result<f64, SoftEtherError> {
+        let ret = strtable::trim_suffix(src, "SM_ST_NUM_PACKET_STR")
+            .replace(',', "")
             .parse()?;
         Ok(ret)
     }
 
-    fn decode_bytes(src: &str) -> Result<f64, Box<dyn Error>> {
-        let ret = String::from(src)
-            .replace(",", "")
-            .replace(" バイト", "")
-            .replace(" bytes", "")
-            .replace(" 字节", "")
+    fn decode_bytes(src: &str) -> Result<f64, SoftEtherError> {
+        let ret = strtable::trim_suffix(src, "SM_ST_SIZE_BYTE_STR")
+            .replace(',', "")
             .parse()?;
         Ok(ret)
     }
 
-    fn decode_connections(src: &str) -> Result<(f64, f64), Box<dyn Error>> {
-        if !src.contains('/') {
-            Ok((0.0, 0.0))
-        } else {
-            let src: Vec<_> = src.split('/').collect();
-            let ret0: f64 = src[0].trim().parse()?;
-            let ret1: f64 = src[1].trim().parse()?;
-            Ok((ret0, ret1))
+    // vpncmd prints local time and may insert the day of week ( e.g. "2020-04-08 (Wed) 09:25:49" ).
+    // A datetime in an unknown format only skips the timestamp metric, not the whole hub status.
+    fn decode_datetime(src: &str) -> Option<NaiveDateTime> {
+        if !src.starts_with(|x: char| x.is_ascii_digit()) {
+            return None;
+        }
+        let datetime: Vec<_> = src
+            .split_whitespace()
+            .filter(|x| !x.starts_with('('))
+            .collect();
+        match NaiveDateTime::parse_from_str(&datetime.join(" "), "%Y-%m-%d %H:%M:%S") {
+            Ok(x) => Some(x),
+            Err(x) => {
+                warn!("Datetime decode failed: {} ( {} )", src, x);
+                None
+            }
+        }
+    }
+
+    // VLAN ID is "－" ( Japanese ) or "-" if the session doesn't belong to VLAN
+    fn decode_vlan_id(src: &str) -> Result<Option<u16>, SoftEtherError> {
+        match src.trim() {
+            "" | "－" | "-" => Ok(None),
+            x => Ok(Some(x.parse()?)),
         }
     }
+
+    // TCP connections are "current / max", or "なし" ( None ) for sessions without TCP connections.
+    // Other values ( e.g. "None" of a language which is not in strtable ) are treated as no connection,
+    // so that a session list is not failed by the column which is informational only.
+    fn decode_connections(src: &str) -> (f64, f64) {
+        if let Some((x, y)) = src.split_once('/') {
+            if let (Ok(x), Ok(y)) = (x.trim().parse(), y.trim().parse()) {
+                return (x, y);
+            }
+        }
+        if !strtable::is(src.trim(), "SM_SESS_NO_TCP") {
+            debug!("Unknown TCP connections are treated as 0: {}", src);
+        }
+        (0.0, 0.0)
+    }
 }
 
-#[derive(Debug)]
+// Fields of StatusGet which are expected in every version of vpncmd
+static HUB_STATUS_FIELDS: &[&str] = &[
+    "SM_HUB_STATUS_HUBNAME",
+    "SM_HUB_STATUS_ONLINE",
+    "SM_HUB_TYPE",
+    "SM_HUB_SECURE_NAT",
+    "SM_HUB_NUM_SESSIONS",
+    "SM_HUB_NUM_SESSIONS_CLIENT",
+    "SM_HUB_NUM_SESSIONS_BRIDGE",
+    "SM_HUB_NUM_ACCESSES",
+    "SM_HUB_NUM_USERS",
+    "SM_HUB_NUM_GROUPS",
+    "SM_HUB_NUM_MAC_TABLES",
+    "SM_HUB_NUM_IP_TABLES",
+    "SM_HUB_NUM_LOGIN",
+    "SM_HUB_LAST_LOGIN_TIME",
+    "SM_HUB_LAST_COMM_TIME",
+    "SM_HUB_CREATED_TIME",
+    "SM_ST_SEND_UCAST_NUM",
+    "SM_ST_SEND_UCAST_SIZE",
+    "SM_ST_SEND_BCAST_NUM",
+    "SM_ST_SEND_BCAST_SIZE",
+    "SM_ST_RECV_UCAST_NUM",
+    "SM_ST_RECV_UCAST_SIZE",
+    "SM_ST_RECV_BCAST_NUM",
+    "SM_ST_RECV_BCAST_SIZE",
+];
+
+#[derive(Clone, Debug)]
+pub struct ParseReport {
+    pub command: &'static str,
+    pub missing: Vec<&'static str>,
+    pub unknown: Vec<String>,
+}
+
+impl ParseReport {
+    pub fn new(command: &'static str, expected: &[&'static str]) -> ParseReport {
+        ParseReport {
+            command,
+            missing: expected.to_vec(),
+            unknown: Vec::new(),
+        }
+    }
+
+    fn seen(&mut self, id: &str) {
+        self.missing.retain(|x| *x != id);
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
 pub struct HubStatus {
+    #[serde(skip)]
+    pub report: ParseReport,
     pub name: String,
     pub online: bool,
+    pub hub_type: HubType,
     pub secure_nat: bool,
     pub sessions: f64,
     pub sessions_client: f64,
@@ -313,6 +760,9 @@ pub struct HubStatus {
     pub mac_tables: f64,
     pub ip_tables: f64,
     pub logins: f64,
+    pub last_login: Option<NaiveDateTime>,
+    pub last_communication: Option<NaiveDateTime>,
+    pub created: Option<NaiveDateTime>,
     pub outgoing_unicast_packets: f64,
     pub outgoing_unicast_bytes: f64,
     pub outgoing_broadcast_packets: f64,
@@ -326,8 +776,10 @@ pub struct HubStatus {
 impl HubStatus {
     pub fn new() -> HubStatus {
         HubStatus {
+            report: ParseReport::new("StatusGet", HUB_STATUS_FIELDS),
             name: String::from(""),
             online: false,
+            hub_type: HubType::Unknown,
             secure_nat: false,
             sessions: 0.0,
             sessions_client: 0.0,
@@ -338,6 +790,9 @@ impl HubStatus {
             mac_tables: 0.0,
             ip_tables: 0.0,
             logins: 0.0,
+            last_login: None,
+            last_communication: None,
+            created: None,
             outgoing_unicast_packets: 0.0,
             outgoing_unicast_bytes: 0.0,
             outgoing_broadcast_packets: 0.0,
@@ -350,10 +805,11 @@ impl HubStatus {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub struct HubSession {
+    pub protocol: SessionProtocol,
     pub name: String,
-    pub vlan_id: String,
+    pub vlan_id: Option<u16>,
     pub location: String,
     pub user: String,
     pub source: String,
@@ -362,83 +818,534 @@ pub struct HubSession {
     pub transfer_packets: f64,
 }
 
+// Session of a local user for tests
+#[cfg(test)]
+impl HubSession {
+    pub fn new(name: &str, user: &str, transfer_bytes: f64, transfer_packets: f64) -> HubSession {
+        HubSession {
+            protocol: SessionProtocol::from_session_name(name),
+            name: String::from(name),
+            vlan_id: None,
+            location: String::from("Local Session"),
+            user: String::from(user),
+            source: String::from("192.168.0.1"),
+            connections: (1.0, 1.0),
+            transfer_bytes,
+            transfer_packets,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HubType {
+    Standalone,
+    Static,
+    Dynamic,
+    Unknown,
+}
+
+impl HubType {
+    pub fn from_str(src: &str) -> HubType {
+        if strtable::is(src, "SM_HUB_STANDALONE") {
+            HubType::Standalone
+        } else if strtable::is(src, "SM_HUB_STATIC") {
+            HubType::Static
+        } else if strtable::is(src, "SM_HUB_DYNAMIC") {
+            HubType::Dynamic
+        } else {
+            HubType::Unknown
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HubType::Standalone => "standalone",
+            HubType::Static => "static",
+            HubType::Dynamic => "dynamic",
+            HubType::Unknown => "unknown",
+        }
+    }
+}
+
+impl Serialize for HubType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SessionProtocol {
+    SoftEther,
+    OpenVpn,
+    Sstp,
+    L2tp,
+    EtherIp,
+    L2tpv3,
+    LocalBridge,
+    SecureNat,
+    Cascade,
+}
+
+impl SessionProtocol {
+    pub const ALL: [SessionProtocol; 9] = [
+        SessionProtocol::SoftEther,
+        SessionProtocol::OpenVpn,
+        SessionProtocol::Sstp,
+        SessionProtocol::L2tp,
+        SessionProtocol::EtherIp,
+        SessionProtocol::L2tpv3,
+        SessionProtocol::LocalBridge,
+        SessionProtocol::SecureNat,
+        SessionProtocol::Cascade,
+    ];
+
+    // SoftEther embeds the connection method into the session name
+    // ( e.g. "SID-USER-[OPENVPN_L3]-5", "SID-LOCALBRIDGE-1" )
+    pub fn from_session_name(name: &str) -> SessionProtocol {
+        if name.contains("-[OPENVPN") {
+            SessionProtocol::OpenVpn
+        } else if name.contains("-[SSTP]") {
+            SessionProtocol::Sstp
+        } else if name.contains("-[L2TP]") {
+            SessionProtocol::L2tp
+        } else if name.contains("-[ETHERIP]") {
+            SessionProtocol::EtherIp
+        } else if name.contains("-[L2TPV3]") {
+            SessionProtocol::L2tpv3
+        } else if name.starts_with("SID-LOCALBRIDGE-") {
+            SessionProtocol::LocalBridge
+        } else if name.starts_with("SID-SECURENAT-") {
+            SessionProtocol::SecureNat
+        } else if name.starts_with("SID-LINK-") {
+            SessionProtocol::Cascade
+        } else {
+            SessionProtocol::SoftEther
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SessionProtocol::SoftEther => "softether",
+            SessionProtocol::OpenVpn => "openvpn",
+            SessionProtocol::Sstp => "sstp",
+            SessionProtocol::L2tp => "l2tp",
+            SessionProtocol::EtherIp => "etherip",
+            SessionProtocol::L2tpv3 => "l2tpv3",
+            SessionProtocol::LocalBridge => "bridge",
+            SessionProtocol::SecureNat => "securenat",
+            SessionProtocol::Cascade => "cascade",
+        }
+    }
+}
+
+impl Serialize for SessionProtocol {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+#[derive(Debug)]
+pub struct IpsecStatus {
+    pub l2tp_ipsec: bool,
+    pub l2tp_raw: bool,
+    pub etherip_ipsec: bool,
+}
+
+impl IpsecStatus {
+    pub fn new() -> IpsecStatus {
+        IpsecStatus {
+            l2tp_ipsec: false,
+            l2tp_raw: false,
+            etherip_ipsec: false,
+        }
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug)]
+pub struct EtherIpClient {
+    pub id: String,
+    pub hub: String,
+    pub user: String,
+}
+
+#[derive(Debug)]
+pub struct HubConfig {
+    pub anonymous_enum: bool,
+    pub trusted_cas: f64,
+    pub crls: f64,
+    pub radius: bool,
+    pub security_log: bool,
+    pub packet_log: bool,
+}
+
+impl HubConfig {
+    pub fn new() -> HubConfig {
+        HubConfig {
+            anonymous_enum: true,
+            trusted_cas: 0.0,
+            crls: 0.0,
+            radius: false,
+            security_log: false,
+            packet_log: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct DdnsStatus {
+    pub hostname: String,
+    pub ipv4: String,
+    pub ipv6: String,
+}
+
+impl DdnsStatus {
+    pub fn new() -> DdnsStatus {
+        DdnsStatus {
+            hostname: String::from(""),
+            ipv4: String::from(""),
+            ipv6: String::from(""),
+        }
+    }
+
+    // The global addresses are filled by the DDNS server only after a successful registration
+    pub fn registered(&self) -> bool {
+        !self.hostname.is_empty() && (!self.ipv4.is_empty() || !self.ipv6.is_empty())
+    }
+}
+
+#[derive(Debug)]
+pub struct AzureStatus {
+    pub enabled: bool,
+    pub connected: bool,
+    pub hostname: String,
+}
+
+impl AzureStatus {
+    pub fn new() -> AzureStatus {
+        AzureStatus {
+            enabled: false,
+            connected: false,
+            hostname: String::from(""),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    // Hand-written vpncmd outputs of each language in testdata directory.
+    // Some labels are not verified with real vpncmd output ( see testdata/README.md ).
+    macro_rules! fixtures {
+        ($cmd:literal) => {
+            [
+                ("ja", include_str!(concat!("../testdata/ja/", $cmd, ".csv"))),
+                ("en", include_str!(concat!("../testdata/en/", $cmd, ".csv"))),
+                ("cn", include_str!(concat!("../testdata/cn/", $cmd, ".csv"))),
+            ]
+        };
+    }
+
+    fn datetime(src: &str) -> Option<NaiveDateTime> {
+        NaiveDateTime::parse_from_str(src, "%Y-%m-%d %H:%M:%S").ok()
+    }
+
     #[test]
     fn test_hub_status() {
-        let src = r#"項目,値
-仮想 HUB 名,DEFAULT
-状態,オンライン
-種類,スタンドアロン
-SecureNAT 機能,無効
-セッション数,4
-セッション数 (クライアント),3
-セッション数 (ブリッジ),0
-アクセスリスト数,0
-ユーザー数,1
-グループ数,0
-MAC テーブル数,134
-IP テーブル数,211
-ログイン回数,18965
-最終ログイン日時,2020-04-08 09:25:49
-最終通信日時,2020-04-08 11:31:43
-作成日時,2018-01-16 10:04:05
-送信ユニキャストパケット数,"7,262,679,895 パケット"
-送信ユニキャスト合計サイズ,"4,153,388,417,848 バイト"
-送信ブロードキャストパケット数,"1,756,889,863 パケット"
-送信ブロードキャスト合計サイズ,"256,781,466,202 バイト"
-受信ユニキャストパケット数,"8,840,585,104 パケット"
-受信ユニキャスト合計サイズ,"4,676,951,155,757 バイト"
-受信ブロードキャストパケット数,"976,264,699 パケット"
-受信ブロードキャスト合計サイズ,"138,170,046,309 バイト""#;
-
-        let status = SoftEtherReader::decode_hub_status(src.as_bytes()).unwrap();
-        assert_eq!(status.name, String::from("DEFAULT"));
-        assert_eq!(status.online, true);
-        assert_eq!(status.secure_nat, false);
-        assert_eq!(status.sessions, 4.0);
-        assert_eq!(status.sessions_client, 3.0);
-        assert_eq!(status.sessions_bridge, 0.0);
-        assert_eq!(status.access_lists, 0.0);
-        assert_eq!(status.users, 1.0);
-        assert_eq!(status.groups, 0.0);
-        assert_eq!(status.mac_tables, 134.0);
-        assert_eq!(status.ip_tables, 211.0);
-        assert_eq!(status.logins, 18965.0);
-        assert_eq!(status.outgoing_unicast_packets, 7262679895.0);
-        assert_eq!(status.outgoing_unicast_bytes, 4153388417848.0);
-        assert_eq!(status.outgoing_broadcast_packets, 1756889863.0);
-        assert_eq!(status.outgoing_broadcast_bytes, 256781466202.0);
-        assert_eq!(status.incoming_unicast_packets, 8840585104.0);
-        assert_eq!(status.incoming_unicast_bytes, 4676951155757.0);
-        assert_eq!(status.incoming_broadcast_packets, 976264699.0);
-        assert_eq!(status.incoming_broadcast_bytes, 138170046309.0);
+        for (lang, src) in &fixtures!("StatusGet") {
+            let status = SoftEtherReader::decode_hub_status(src.as_bytes()).unwrap();
+            assert_eq!(status.name, String::from("DEFAULT"), "{}", lang);
+            assert!(status.online, "{}", lang);
+            assert_eq!(status.hub_type, HubType::Standalone, "{}", lang);
+            assert!(!status.secure_nat, "{}", lang);
+            assert_eq!(status.sessions, 4.0, "{}", lang);
+            assert_eq!(status.sessions_client, 3.0, "{}", lang);
+            assert_eq!(status.sessions_bridge, 0.0, "{}", lang);
+            assert_eq!(status.access_lists, 0.0, "{}", lang);
+            assert_eq!(status.users, 1.0, "{}", lang);
+            assert_eq!(status.groups, 0.0, "{}", lang);
+            assert_eq!(status.mac_tables, 134.0, "{}", lang);
+            assert_eq!(status.ip_tables, 211.0, "{}", lang);
+            assert_eq!(status.logins, 18965.0, "{}", lang);
+            assert_eq!(
+                status.last_login,
+                datetime("2020-04-08 09:25:49"),
+                "{}",
+                lang
+            );
+            assert_eq!(
+                status.last_communication,
+                datetime("2020-04-08 11:31:43"),
+                "{}",
+                lang
+            );
+            assert_eq!(status.created, datetime("2018-01-16 10:04:05"), "{}", lang);
+            assert_eq!(status.outgoing_unicast_packets, 7262679895.0, "{}", lang);
+            assert_eq!(status.outgoing_unicast_bytes, 4153388417848.0, "{}", lang);
+            assert_eq!(status.outgoing_broadcast_packets, 1756889863.0, "{}", lang);
+            assert_eq!(status.outgoing_broadcast_bytes, 256781466202.0, "{}", lang);
+            assert_eq!(status.incoming_unicast_packets, 8840585104.0, "{}", lang);
+            assert_eq!(status.incoming_unicast_bytes, 4676951155757.0, "{}", lang);
+            assert_eq!(status.incoming_broadcast_packets, 976264699.0, "{}", lang);
+            assert_eq!(status.incoming_broadcast_bytes, 138170046309.0, "{}", lang);
+            assert!(status.report.missing.is_empty(), "{}", lang);
+            assert!(status.report.unknown.is_empty(), "{}", lang);
+        }
     }
 
     #[test]
     fn test_hub_session() {
-        let src = r#"セッション名,VLAN ID,場所,ユーザー名,接続元ホスト名,TCP コネクション,転送バイト数,転送パケット数
-SID-LOCALBRIDGE-1,－,ローカルセッション,Local Bridge,Ethernet ブリッジ,なし,"294,035,917,956","1,380,393,323"
-SID-XXXX-1047,－,ローカルセッション,xxxx,xxx.example.com,2 / 2,"82,691,861","322,784""#;
+        for (lang, src) in &fixtures!("SessionList") {
+            let sessions = SoftEtherReader::decode_hub_sessions(src.as_bytes()).unwrap();
+            assert_eq!(sessions.len(), 4, "{}", lang);
+            assert_eq!(
+                sessions[0].name,
+                String::from("SID-LOCALBRIDGE-1"),
+                "{}",
+                lang
+            );
+            assert_eq!(
+                sessions[0].protocol,
+                SessionProtocol::LocalBridge,
+                "{}",
+                lang
+            );
+            assert_eq!(sessions[0].vlan_id, None, "{}", lang);
+            assert_eq!(sessions[0].user, String::from("Local Bridge"), "{}", lang);
+            assert_eq!(sessions[0].connections, (0.0, 0.0), "{}", lang);
+            assert_eq!(sessions[0].transfer_bytes, 294035917956.0, "{}", lang);
+            assert_eq!(sessions[0].transfer_packets, 1380393323.0, "{}", lang);
+            assert_eq!(sessions[1].name, String::from("SID-XXXX-1047"), "{}", lang);
+            assert_eq!(sessions[1].protocol, SessionProtocol::SoftEther, "{}", lang);
+            assert_eq!(sessions[1].vlan_id, None, "{}", lang);
+            assert_eq!(sessions[1].user, String::from("xxxx"), "{}", lang);
+            assert_eq!(
+                sessions[1].source,
+                String::from("xxx.example.com"),
+                "{}",
+                lang
+            );
+            assert_eq!(sessions[1].connections, (2.0, 2.0), "{}", lang);
+            assert_eq!(sessions[1].transfer_bytes, 82691861.0, "{}", lang);
+            assert_eq!(sessions[1].transfer_packets, 322784.0, "{}", lang);
+            assert_eq!(sessions[2].protocol, SessionProtocol::OpenVpn, "{}", lang);
+            assert_eq!(sessions[2].vlan_id, Some(10), "{}", lang);
+            assert_eq!(sessions[2].connections, (0.0, 0.0), "{}", lang);
+            assert_eq!(sessions[2].transfer_bytes, 1024.0, "{}", lang);
+            assert_eq!(sessions[2].transfer_packets, 8.0, "{}", lang);
+            assert_eq!(sessions[3].protocol, SessionProtocol::L2tp, "{}", lang);
+            assert_eq!(sessions[3].source, String::from("192.0.2.2"), "{}", lang);
+        }
 
+        let src = include_str!("../testdata/ja/SessionList.csv");
         let sessions = SoftEtherReader::decode_hub_sessions(src.as_bytes()).unwrap();
-        assert_eq!(sessions[0].name, String::from("SID-LOCALBRIDGE-1"));
-        assert_eq!(sessions[0].vlan_id, String::from("－"));
         assert_eq!(sessions[0].location, String::from("ローカルセッション"));
-        assert_eq!(sessions[0].user, String::from("Local Bridge"));
         assert_eq!(sessions[0].source, String::from("Ethernet ブリッジ"));
+    }
+
+    #[test]
+    fn test_openvpn_status() {
+        for (lang, src) in &fixtures!("OpenVpnGet") {
+            let enabled = SoftEtherReader::decode_openvpn_status(src.as_bytes()).unwrap();
+            assert!(enabled, "{}", lang);
+        }
+    }
+
+    #[test]
+    fn test_sstp_status() {
+        for (lang, src) in &fixtures!("SstpGet") {
+            let enabled = SoftEtherReader::decode_sstp_status(src.as_bytes()).unwrap();
+            assert!(!enabled, "{}", lang);
+        }
+    }
+
+    #[test]
+    fn test_ipsec_status() {
+        for (lang, src) in &fixtures!("IPsecGet") {
+            let status = SoftEtherReader::decode_ipsec_status(src.as_bytes()).unwrap();
+            assert!(status.l2tp_ipsec, "{}", lang);
+            assert!(!status.l2tp_raw, "{}", lang);
+            assert!(status.etherip_ipsec, "{}", lang);
+        }
+    }
+
+    #[test]
+    fn test_etherip_clients() {
+        for (lang, src) in &fixtures!("EtherIpClientList") {
+            let clients = SoftEtherReader::decode_etherip_clients(src.as_bytes()).unwrap();
+            assert_eq!(clients.len(), 2, "{}", lang);
+            assert_eq!(clients[0].id, String::from("site1"), "{}", lang);
+            assert_eq!(clients[0].hub, String::from("DEFAULT"), "{}", lang);
+            assert_eq!(clients[0].user, String::from("etherip1"), "{}", lang);
+            assert_eq!(clients[1].id, String::from("site2"), "{}", lang);
+            assert_eq!(clients[1].hub, String::from("HUB2"), "{}", lang);
+            assert_eq!(clients[1].user, String::from("etherip2"), "{}", lang);
+        }
+    }
+
+    #[test]
+    fn test_hub_config() {
+        for (lang, src) in &fixtures!("OptionsGet") {
+            let config = SoftEtherReader::decode_hub_options(src.as_bytes()).unwrap();
+            assert!(!config.anonymous_enum, "{}", lang);
+        }
+        for (lang, src) in &fixtures!("CAList") {
+            let count = SoftEtherReader::decode_count(src.as_bytes()).unwrap();
+            assert_eq!(count, 2.0, "{}", lang);
+        }
+        for (lang, src) in &fixtures!("CrlList") {
+            let count = SoftEtherReader::decode_count(src.as_bytes()).unwrap();
+            assert_eq!(count, 1.0, "{}", lang);
+        }
+        for (lang, src) in &fixtures!("RadiusServerGet") {
+            let radius = SoftEtherReader::decode_hub_radius(src.as_bytes()).unwrap();
+            assert!(radius, "{}", lang);
+        }
+        for (lang, src) in &fixtures!("LogGet") {
+            let (security_log, packet_log) =
+                SoftEtherReader::decode_hub_log(src.as_bytes()).unwrap();
+            assert!(security_log, "{}", lang);
+            assert!(!packet_log, "{}", lang);
+        }
+    }
+
+    #[test]
+    fn test_ddns_status() {
+        for (lang, src) in &fixtures!("DDnsGetStatus") {
+            let status = SoftEtherReader::decode_ddns_status(src.as_bytes()).unwrap();
+            assert_eq!(
+                status.hostname,
+                String::from("vpn123456789.softether.net"),
+                "{}",
+                lang
+            );
+            assert_eq!(status.ipv4, String::from("203.0.113.10"), "{}", lang);
+            assert_eq!(status.ipv6, String::from(""), "{}", lang);
+            assert!(status.registered(), "{}", lang);
+        }
+    }
+
+    #[test]
+    fn test_azure_status() {
+        for (lang, src) in &fixtures!("VpnAzureGetStatus") {
+            let status = SoftEtherReader::decode_azure_status(src.as_bytes()).unwrap();
+            assert!(status.enabled, "{}", lang);
+            assert!(!status.connected, "{}", lang);
+            assert_eq!(
+                status.hostname,
+                String::from("vpn123456789.vpnazure.net"),
+                "{}",
+                lang
+            );
+        }
+    }
+
+    #[test]
+    fn test_session_columns() {
+        assert_eq!(SoftEtherReader::decode_vlan_id("－").unwrap(), None);
+        assert_eq!(SoftEtherReader::decode_vlan_id("100").unwrap(), Some(100));
+        assert_eq!(SoftEtherReader::decode_connections("なし"), (0.0, 0.0));
+        assert_eq!(SoftEtherReader::decode_connections("1 / 32"), (1.0, 32.0));
+        assert_eq!(SoftEtherReader::decode_connections("xxx"), (0.0, 0.0));
+        assert_eq!(SoftEtherReader::decode_connections("x / 32"), (0.0, 0.0));
+
+        // An unknown value of TCP connections doesn't fail the session list
+        let src = "Session Name,VLAN ID,Location,User Name,Source Host Name,TCP Connections,Transfer Bytes,Transfer Packets\n\
+                   SID-USER1-1,-,Local Session,user1,192.0.2.1,Keine,\"1,024\",8\n";
+        let sessions = SoftEtherReader::decode_hub_sessions(src.as_bytes()).unwrap();
         assert_eq!(sessions[0].connections, (0.0, 0.0));
-        assert_eq!(sessions[0].transfer_bytes, 294035917956.0);
-        assert_eq!(sessions[0].transfer_packets, 1380393323.0);
-        assert_eq!(sessions[1].name, String::from("SID-XXXX-1047"));
-        assert_eq!(sessions[1].vlan_id, String::from("－"));
-        assert_eq!(sessions[1].location, String::from("ローカルセッション"));
-        assert_eq!(sessions[1].user, String::from("xxxx"));
-        assert_eq!(sessions[1].source, String::from("xxx.example.com"));
-        assert_eq!(sessions[1].connections, (2.0, 2.0));
-        assert_eq!(sessions[1].transfer_bytes, 82691861.0);
-        assert_eq!(sessions[1].transfer_packets, 322784.0);
+        assert_eq!(sessions[0].transfer_bytes, 1024.0);
+        assert_eq!(
+            SoftEtherReader::decode_packets("1,024 packets").unwrap(),
+            1024.0
+        );
+        assert_eq!(SoftEtherReader::decode_packets("1,024").unwrap(), 1024.0);
+    }
+
+    #[test]
+    fn test_datetime() {
+        let datetime = SoftEtherReader::decode_datetime("2020-04-08 (Wed) 09:25:49");
+        assert_eq!(
+            datetime,
+            NaiveDateTime::parse_from_str("2020-04-08 09:25:49", "%Y-%m-%d %H:%M:%S").ok()
+        );
+        assert_eq!(SoftEtherReader::decode_datetime("(None)"), None);
+        assert_eq!(SoftEtherReader::decode_datetime("2020/04/08 9:25:49"), None);
+    }
+
+    #[test]
+    fn test_hub_type() {
+        let src = r#"Item,Value
+Virtual Hub Name,HUB1
+Status,Online
+Type,Dynamic"#;
+
+        let status = SoftEtherReader::decode_hub_status(src.as_bytes()).unwrap();
+        assert_eq!(status.hub_type, HubType::Dynamic);
+
+        let src = r#"项目,值
+虚拟 HUB 名称,HUB1
+状态,在线
+类型,静态"#;
+
+        let status = SoftEtherReader::decode_hub_status(src.as_bytes()).unwrap();
+        assert_eq!(status.hub_type, HubType::Static);
+    }
+
+    #[test]
+    fn test_parse_report() {
+        let src = r#"Item,Value
+Virtual Hub Name,HUB1
+Status,Online
+Number of Sessions,4"#;
+
+        let status = SoftEtherReader::decode_hub_status(src.as_bytes()).unwrap();
+        assert!(!status.report.missing.contains(&"SM_HUB_STATUS_HUBNAME"));
+        assert!(!status.report.missing.contains(&"SM_HUB_STATUS_ONLINE"));
+        assert!(status.report.missing.contains(&"SM_HUB_NUM_SESSIONS"));
+        assert_eq!(status.report.missing.len(), HUB_STATUS_FIELDS.len() - 2);
+        assert_eq!(
+            status.report.unknown,
+            vec![String::from("Number of Sessions")]
+        );
+    }
+
+    #[test]
+    fn test_error_kind() {
+        let err = SoftEtherError::from_code(9, String::from("Error occurred. (Error code: 9)"));
+        assert_eq!(err.kind(), "auth_failed");
+        let err = SoftEtherError::from_code(8, String::from("Error occurred. (Error code: 8)"));
+        assert_eq!(err.kind(), "hub_not_found");
+        let err = SoftEtherError::from_code(1, String::from("Error occurred. (Error code: 1)"));
+        assert_eq!(err.kind(), "unreachable");
+        let err = SoftEtherReader::hub_status("/nonexistent/vpncmd", "localhost", "HUB", "")
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), "vpncmd_missing");
+        let err = SoftEtherReader::decode_hub_status("Item,Value\nSessions,x".as_bytes())
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), "parse");
+    }
+
+    #[test]
+    fn test_command_line() {
+        let args: Vec<String> = vec![
+            "vpn.example.com",
+            "/SERVER",
+            "/HUB:HUB1",
+            "/PASSWORD:secret",
+            "/CSV",
+            "/CMD",
+            "StatusGet",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect();
+        assert_eq!(
+            SoftEtherReader::command_line("vpncmd", &args),
+            "vpncmd vpn.example.com /SERVER /HUB:HUB1 /PASSWORD:******** /CSV /CMD StatusGet"
+        );
     }
 }
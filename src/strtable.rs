@@ -0,0 +1,319 @@
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+
+// Localized labels of vpncmd output.
+// Each entry is keyed by the string table ID of SoftEther VPN ( see src/bin/hamcore/strtable_*.stb ),
+// and has the labels in Japanese, English and Simplified Chinese.
+// Some labels are not verified with real vpncmd output ( see testdata/README.md ).
+// Korean and Traditional Chinese are not supported, because their labels couldn't be taken from
+// strtable_ko.stb / strtable_tw.stb.
+static TABLE: &[(&str, &[&str])] = &[
+    // StatusGet
+    (
+        "SM_HUB_STATUS_HUBNAME",
+        &["仮想 HUB 名", "Virtual Hub Name", "虚拟 HUB 名称"],
+    ),
+    ("SM_HUB_STATUS_ONLINE", &["状態", "Status", "状态"]),
+    ("SM_HUB_TYPE", &["種類", "Type", "类型"]),
+    (
+        "SM_HUB_SECURE_NAT",
+        &["SecureNAT 機能", "SecureNAT", "SecureNAT 机能"],
+    ),
+    (
+        "SM_HUB_NUM_SESSIONS",
+        &["セッション数", "Sessions", "会话数"],
+    ),
+    (
+        "SM_HUB_NUM_SESSIONS_CLIENT",
+        &[
+            "セッション数 (クライアント)",
+            "Sessions (Client)",
+            "会话数 (客户端)",
+        ],
+    ),
+    (
+        "SM_HUB_NUM_SESSIONS_BRIDGE",
+        &[
+            "セッション数 (ブリッジ)",
+            "Sessions (Bridge)",
+            "会话数 (网桥)",
+        ],
+    ),
+    (
+        "SM_HUB_NUM_ACCESSES",
+        &["アクセスリスト数", "Access Lists", "访问列表"],
+    ),
+    ("SM_HUB_NUM_USERS", &["ユーザー数", "Users", "用户数"]),
+    ("SM_HUB_NUM_GROUPS", &["グループ数", "Groups", "组数"]),
+    (
+        "SM_HUB_NUM_MAC_TABLES",
+        &["MAC テーブル数", "MAC Tables", "MAC 表数"],
+    ),
+    (
+        "SM_HUB_NUM_IP_TABLES",
+        &["IP テーブル数", "IP Tables", "IP 表数"],
+    ),
+    (
+        "SM_HUB_NUM_LOGIN",
+        &["ログイン回数", "Num Logins", "登录次数"],
+    ),
+    (
+        "SM_HUB_LAST_LOGIN_TIME",
+        &["最終ログイン日時", "Last Login", "最后登录时间"],
+    ),
+    (
+        "SM_HUB_LAST_COMM_TIME",
+        &["最終通信日時", "Last Communication", "最后通信时间"],
+    ),
+    (
+        "SM_HUB_CREATED_TIME",
+        &["作成日時", "Created at", "创建日期"],
+    ),
+    (
+        "SM_ST_SEND_UCAST_NUM",
+        &[
+            "送信ユニキャストパケット数",
+            "Outgoing Unicast Packets",
+            "发送单播数据包",
+        ],
+    ),
+    (
+        "SM_ST_SEND_UCAST_SIZE",
+        &[
+            "送信ユニキャスト合計サイズ",
+            "Outgoing Unicast Total Size",
+            "发送单播总量",
+        ],
+    ),
+    (
+        "SM_ST_SEND_BCAST_NUM",
+        &[
+            "送信ブロードキャストパケット数",
+            "Outgoing Broadcast Packets",
+            "发送广播数据包",
+        ],
+    ),
+    (
+        "SM_ST_SEND_BCAST_SIZE",
+        &[
+            "送信ブロードキャスト合計サイズ",
+            "Outgoing Broadcast Total Size",
+            "发送广播总量",
+        ],
+    ),
+    (
+        "SM_ST_RECV_UCAST_NUM",
+        &[
+            "受信ユニキャストパケット数",
+            "Incoming Unicast Packets",
+            "接收单播数据包",
+        ],
+    ),
+    (
+        "SM_ST_RECV_UCAST_SIZE",
+        &[
+            "受信ユニキャスト合計サイズ",
+            "Incoming Unicast Total Size",
+            "接收单播总量",
+        ],
+    ),
+    (
+        "SM_ST_RECV_BCAST_NUM",
+        &[
+            "受信ブロードキャストパケット数",
+            "Incoming Broadcast Packets",
+            "接收广播数据包",
+        ],
+    ),
+    (
+        "SM_ST_RECV_BCAST_SIZE",
+        &[
+            "受信ブロードキャスト合計サイズ",
+            "Incoming Broadcast Total Size",
+            "接收广播总量",
+        ],
+    ),
+    // OpenVpnGet / SstpGet
+    (
+        "CMD_OpenVpnGet_PRINT_Enabled",
+        &[
+            "OpenVPN 互換サーバー機能を使用",
+            "Use OpenVPN Clone Server Function",
+            "使用 OpenVPN 兼容服务器功能",
+        ],
+    ),
+    (
+        "CMD_SstpGet_PRINT_Enabled",
+        &[
+            "MS-SSTP VPN 互換サーバー機能を使用",
+            "Use MS-SSTP VPN Clone Server Function",
+            "使用 MS-SSTP VPN 兼容服务器功能",
+        ],
+    ),
+    // OptionsGet / RadiusServerGet / LogGet
+    (
+        "CMD_OptionsGet_ENUM",
+        &[
+            "匿名ユーザーへの列挙の禁止",
+            "Don't Enumerate for Anonymous Users",
+            "禁止对匿名用户列举",
+        ],
+    ),
+    (
+        "CMD_RadiusServerGet_STATUS",
+        &[
+            "RADIUS サーバーの使用",
+            "Use RADIUS Server",
+            "使用 RADIUS 服务器",
+        ],
+    ),
+    (
+        "CMD_Log_SecurityLog",
+        &[
+            "セキュリティログの保存",
+            "Save Security Log",
+            "保存安全日志",
+        ],
+    ),
+    (
+        "CMD_Log_PacketLog",
+        &["パケットログの保存", "Save Packet Log", "保存数据包日志"],
+    ),
+    // DDnsGetStatus
+    (
+        "CMD_DDnsGetStatus_FQDN",
+        &[
+            "割り当てられたダイナミック DNS ホスト名 (完全)",
+            "Assigned Dynamic DNS Hostname (Full)",
+            "分配的动态 DNS 主机名 (完整)",
+        ],
+    ),
+    (
+        "CMD_DDnsGetStatus_IPv4",
+        &[
+            "グローバル IPv4 アドレス",
+            "Global IPv4 Address",
+            "全局 IPv4 地址",
+        ],
+    ),
+    (
+        "CMD_DDnsGetStatus_IPv6",
+        &[
+            "グローバル IPv6 アドレス",
+            "Global IPv6 Address",
+            "全局 IPv6 地址",
+        ],
+    ),
+    // VpnAzureGetStatus
+    (
+        "CMD_VpnAzureGetStatus_PRINT_ENABLED",
+        &[
+            "VPN Azure 機能が有効",
+            "VPN Azure Function is Enabled",
+            "VPN Azure 功能已启用",
+        ],
+    ),
+    (
+        "CMD_VpnAzureGetStatus_PRINT_CONNECTED",
+        &[
+            "VPN Azure クラウドサーバーとの接続が確立",
+            "Connection to VPN Azure Cloud Server is Established",
+            "已建立与 VPN Azure 云服务器的连接",
+        ],
+    ),
+    (
+        "CMD_VpnAzureGetStatus_PRINT_HOSTNAME",
+        &[
+            "VPN Azure 上でのこの VPN Server のホスト名",
+            "Hostname of this VPN Server on VPN Azure Service",
+            "此 VPN Server 在 VPN Azure 服务上的主机名",
+        ],
+    ),
+];
+
+// Localized values of vpncmd output
+static VALUES: &[(&str, &[&str])] = &[
+    ("SM_HUB_ONLINE", &["オンライン", "Online", "在线"]),
+    ("SM_HUB_SECURE_NAT_NO", &["無効", "Disabled", "无效"]),
+    (
+        "SM_HUB_STANDALONE",
+        &["スタンドアロン", "Standalone", "独立"],
+    ),
+    ("SM_HUB_STATIC", &["スタティック", "Static", "静态"]),
+    ("SM_HUB_DYNAMIC", &["ダイナミック", "Dynamic", "动态"]),
+    ("SEC_YES", &["はい", "Yes", "是"]),
+    ("SEC_NO", &["いいえ", "No", "否"]),
+    ("SM_SESS_NO_TCP", &["なし", "None", "无"]),
+    (
+        "SM_ST_NUM_PACKET_STR",
+        &[" パケット", " packets", " 数据包"],
+    ),
+    ("SM_ST_SIZE_BYTE_STR", &[" バイト", " bytes", " 字节"]),
+];
+
+lazy_static! {
+    static ref LABELS: HashMap<&'static str, &'static str> = {
+        let mut ret = HashMap::new();
+        for (id, texts) in TABLE {
+            for text in *texts {
+                ret.insert(*text, *id);
+            }
+        }
+        ret
+    };
+}
+
+// Get the string table ID of a localized label
+pub fn lookup(label: &str) -> Option<&'static str> {
+    LABELS.get(label).copied()
+}
+
+// Check whether a localized value is the string of the string table ID
+pub fn is(value: &str, id: &str) -> bool {
+    localized(id).contains(&value)
+}
+
+// Remove a localized suffix ( e.g. " packets" ) of the string table ID
+pub fn trim_suffix<'a>(value: &'a str, id: &str) -> &'a str {
+    for suffix in localized(id) {
+        if let Some(x) = value.strip_suffix(suffix) {
+            return x;
+        }
+    }
+    value
+}
+
+fn localized(id: &str) -> &'static [&'static str] {
+    VALUES
+        .iter()
+        .find(|(x, _)| *x == id)
+        .map(|(_, x)| *x)
+        .unwrap_or(&[])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unique_labels() {
+        let mut labels = HashMap::new();
+        for (id, texts) in TABLE {
+            for text in *texts {
+                if let Some(x) = labels.insert(*text, *id) {
+                    assert_eq!(x, *id, "\"{}\" is duplicated", text);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_lookup() {
+        assert_eq!(lookup("Virtual Hub Name"), Some("SM_HUB_STATUS_HUBNAME"));
+        assert_eq!(lookup("虚拟 HUB 名称"), Some("SM_HUB_STATUS_HUBNAME"));
+        assert_eq!(lookup("Unknown Item"), None);
+        assert!(is("オンライン", "SM_HUB_ONLINE"));
+        assert!(!is("Offline", "SM_HUB_ONLINE"));
+        assert_eq!(trim_suffix("1,234 数据包", "SM_ST_NUM_PACKET_STR"), "1,234");
+    }
+}
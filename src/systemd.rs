@@ -0,0 +1,159 @@
+use anyhow::Error;
+use std::net::TcpListener;
+use std::time::Duration;
+
+// -------------------------------------------------------------------------------------------------
+// systemd integration
+// -------------------------------------------------------------------------------------------------
+
+// All functions are no-op if the exporter is not started by systemd ( or on non-unix platforms ).
+// NOTIFY_SOCKET and WATCHDOG_* are kept because they are used until the exporter stops.
+// LISTEN_PID and LISTEN_FDS are unset by `sd_notify::listen_fds`, so that they are not inherited by vpncmd.
+
+#[cfg(unix)]
+pub fn notify_ready() {
+    let _ = sd_notify::notify(false, &[sd_notify::NotifyState::Ready]);
+}
+
+#[cfg(unix)]
+pub fn notify_stopping() {
+    let _ = sd_notify::notify(false, &[sd_notify::NotifyState::Stopping]);
+}
+
+#[cfg(unix)]
+pub fn notify_watchdog() {
+    let _ = sd_notify::notify(false, &[sd_notify::NotifyState::Watchdog]);
+}
+
+// WatchdogSec of the service
+#[cfg(unix)]
+pub fn watchdog_timeout() -> Option<Duration> {
+    let mut usec = 0;
+    if sd_notify::watchdog_enabled(false, &mut usec) && usec > 0 {
+        Some(Duration::from_micros(usec))
+    } else {
+        None
+    }
+}
+
+// The first socket passed by socket activation
+#[cfg(unix)]
+pub fn listener() -> Result<Option<TcpListener>, Error> {
+    use std::os::unix::io::FromRawFd;
+
+    match sd_notify::listen_fds()?.next() {
+        Some(fd) => {
+            // The fd is passed by systemd, and is owned by this process only
+            let listener = unsafe { TcpListener::from_raw_fd(fd) };
+            listener.set_nonblocking(true)?;
+            Ok(Some(listener))
+        }
+        None => Ok(None),
+    }
+}
+
+#[cfg(not(unix))]
+pub fn notify_ready() {}
+
+#[cfg(not(unix))]
+pub fn notify_stopping() {}
+
+#[cfg(not(unix))]
+pub fn notify_watchdog() {}
+
+#[cfg(not(unix))]
+pub fn watchdog_timeout() -> Option<Duration> {
+    None
+}
+
+#[cfg(not(unix))]
+pub fn listener() -> Result<Option<TcpListener>, Error> {
+    Ok(None)
+}
+
+// -------------------------------------------------------------------------------------------------
+// Test
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(all(test, unix))]
+pub mod tests {
+    use super::*;
+    use lazy_static::lazy_static;
+    use std::env;
+    use std::ffi::OsString;
+    use std::os::unix::net::UnixDatagram;
+    use std::process;
+    use std::sync::{Mutex, MutexGuard};
+
+    lazy_static! {
+        static ref ENV_LOCK: Mutex<()> = Mutex::new(());
+    }
+
+    // Tests touching the systemd environment variables are serialized by the lock,
+    // and the variables are restored when the guard is dropped.
+    pub struct EnvGuard {
+        saved: Vec<(&'static str, Option<OsString>)>,
+        _lock: MutexGuard<'static, ()>,
+    }
+
+    impl EnvGuard {
+        pub fn new() -> Self {
+            let lock = ENV_LOCK.lock().unwrap_or_else(|x| x.into_inner());
+            let saved = [
+                "NOTIFY_SOCKET",
+                "WATCHDOG_USEC",
+                "WATCHDOG_PID",
+                "LISTEN_PID",
+                "LISTEN_FDS",
+            ]
+            .iter()
+            .map(|&x| {
+                let value = env::var_os(x);
+                env::remove_var(x);
+                (x, value)
+            })
+            .collect();
+            EnvGuard { saved, _lock: lock }
+        }
+    }
+
+    impl Drop for EnvGuard {
+        fn drop(&mut self) {
+            for (key, value) in &self.saved {
+                match value {
+                    Some(x) => env::set_var(key, x),
+                    None => env::remove_var(key),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_notify() {
+        let _env = EnvGuard::new();
+        let path = env::temp_dir().join(format!("softether_exporter_notify_{}", process::id()));
+        let _ = std::fs::remove_file(&path);
+        let sock = UnixDatagram::bind(&path).unwrap();
+        sock.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        env::set_var("NOTIFY_SOCKET", &path);
+        env::set_var("WATCHDOG_USEC", "30000000");
+        env::set_var("WATCHDOG_PID", process::id().to_string());
+
+        let mut buf = [0; 64];
+        notify_ready();
+        let len = sock.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1\n");
+        notify_watchdog();
+        let len = sock.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"WATCHDOG=1\n");
+        notify_stopping();
+        let len = sock.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"STOPPING=1\n");
+        assert_eq!(watchdog_timeout(), Some(Duration::from_secs(30)));
+
+        // Not activated by systemd
+        assert!(listener().unwrap().is_none());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,446 @@
+use anyhow::{anyhow, Error};
+use base64::Engine;
+use lazy_static::lazy_static;
+use log::warn;
+use ring::digest;
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio_rustls::TlsAcceptor;
+
+lazy_static! {
+    // Unknown users are verified against this hash to spend the same time as known users
+    static ref DUMMY_HASH: String = bcrypt::hash("", 10).unwrap();
+    // bcrypt is verified one by one, so that unauthenticated requests can use only one CPU core
+    static ref BCRYPT_LOCK: Mutex<()> = Mutex::new(());
+}
+
+static AUTH_CACHE_SIZE: usize = 100;
+
+// Keys of exporter-toolkit which are accepted but not supported
+static UNSUPPORTED_KEYS: &[&str] = &[
+    "http_server_config",
+    "tls_server_config.prefer_server_cipher_suites",
+];
+
+// Keys of exporter-toolkit which are rejected, because ignoring them weakens TLS or client authentication
+static REJECTED_KEYS: &[&str] = &[
+    "tls_server_config.cert",
+    "tls_server_config.key",
+    "tls_server_config.client_ca",
+    "tls_server_config.cipher_suites",
+    "tls_server_config.curve_preferences",
+    "tls_server_config.max_version",
+    "tls_server_config.client_allowed_sans",
+];
+
+// -------------------------------------------------------------------------------------------------
+// WebConfig
+// -------------------------------------------------------------------------------------------------
+
+// Web config file compatible with Prometheus exporter-toolkit
+#[derive(Debug, Default, Deserialize)]
+pub struct WebConfig {
+    tls_server_config: Option<TlsServerConfig>,
+    #[serde(default)]
+    basic_auth_users: HashMap<String, String>,
+    // Successful verifications keyed by the hash of the credential, because bcrypt is slow by design
+    #[serde(skip)]
+    auth_cache: Mutex<HashSet<Vec<u8>>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TlsServerConfig {
+    cert_file: PathBuf,
+    key_file: PathBuf,
+    client_auth_type: Option<ClientAuthType>,
+    client_ca_file: Option<PathBuf>,
+    min_version: Option<TlsVersion>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+pub enum ClientAuthType {
+    NoClientCert,
+    RequestClientCert,
+    RequireAnyClientCert,
+    VerifyClientCertIfGiven,
+    RequireAndVerifyClientCert,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+pub enum TlsVersion {
+    TLS12,
+    TLS13,
+}
+
+impl WebConfig {
+    pub fn from_file(file: &Path) -> Result<WebConfig, Error> {
+        let mut f = File::open(file)?;
+        let mut s = String::new();
+        f.read_to_string(&mut s)?;
+        let mut unknown = Vec::new();
+        let mut config: WebConfig =
+            serde_ignored::deserialize(serde_yaml::Deserializer::from_str(&s), |x| {
+                // Option is shown as "?" in the path
+                let path = x.to_string();
+                let path: Vec<&str> = path.split('.').filter(|x| *x != "?").collect();
+                unknown.push(path.join("."))
+            })?;
+        for key in unknown {
+            if UNSUPPORTED_KEYS.contains(&key.as_str()) {
+                warn!("{} in {} is not supported and ignored", key, file.display());
+            } else if REJECTED_KEYS.contains(&key.as_str()) {
+                return Err(anyhow!("{} in {} is not supported", key, file.display()));
+            } else {
+                return Err(anyhow!("unknown key {} in {}", key, file.display()));
+            }
+        }
+
+        // Client certificates are always verified by client_ca_file
+        if let Some(ref tls) = config.tls_server_config {
+            if let Some(
+                x @ (ClientAuthType::RequestClientCert | ClientAuthType::RequireAnyClientCert),
+            ) = tls.client_auth_type
+            {
+                return Err(anyhow!(
+                    "client_auth_type {:?} in {} is not supported, use VerifyClientCertIfGiven or RequireAndVerifyClientCert",
+                    x,
+                    file.display()
+                ));
+            }
+        }
+
+        // Relative paths are resolved from the directory of the web config file
+        if let Some(ref mut tls) = config.tls_server_config {
+            let dir = file.parent().unwrap_or_else(|| Path::new(""));
+            tls.cert_file = dir.join(&tls.cert_file);
+            tls.key_file = dir.join(&tls.key_file);
+            tls.client_ca_file = tls.client_ca_file.as_ref().map(|x| dir.join(x));
+        }
+
+        for (user, hash) in &config.basic_auth_users {
+            hash.parse::<bcrypt::HashParts>()
+                .map_err(|x| anyhow!("invalid password hash of user {}: {}", user, x))?;
+        }
+        Ok(config)
+    }
+
+    pub fn tls_acceptor(&self) -> Result<Option<TlsAcceptor>, Error> {
+        let tls = match self.tls_server_config {
+            Some(ref x) => x,
+            None => return Ok(None),
+        };
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let versions: &[&rustls::SupportedProtocolVersion] = match tls.min_version {
+            Some(TlsVersion::TLS13) => &[&rustls::version::TLS13],
+            _ => &[&rustls::version::TLS12, &rustls::version::TLS13],
+        };
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_protocol_versions(versions)?;
+
+        let client_auth_type = tls.client_auth_type.unwrap_or(ClientAuthType::NoClientCert);
+        let builder = match (client_auth_type, &tls.client_ca_file) {
+            (ClientAuthType::NoClientCert, _) => builder.with_no_client_auth(),
+            (_, None) => {
+                return Err(anyhow!(
+                    "client_ca_file is required for {:?}",
+                    client_auth_type
+                ))
+            }
+            (_, Some(ca_file)) => {
+                let mut roots = RootCertStore::empty();
+                for cert in load_certs(ca_file)? {
+                    roots.add(cert)?;
+                }
+                let verifier =
+                    WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+                let verifier = if client_auth_type == ClientAuthType::VerifyClientCertIfGiven {
+                    verifier.allow_unauthenticated().build()?
+                } else {
+                    verifier.build()?
+                };
+                builder.with_client_cert_verifier(verifier)
+            }
+        };
+
+        let certs = load_certs(&tls.cert_file)?;
+        let key = rustls_pemfile::private_key(&mut BufReader::new(File::open(&tls.key_file)?))?
+            .ok_or_else(|| anyhow!("no private key in {}", tls.key_file.display()))?;
+        let config = builder.with_single_cert(certs, key)?;
+
+        Ok(Some(TlsAcceptor::from(Arc::new(config))))
+    }
+
+    // Check the value of Authorization header
+    pub fn authorized(&self, authorization: Option<&str>) -> bool {
+        if self.basic_auth_users.is_empty() {
+            return true;
+        }
+
+        let credential = authorization
+            .and_then(|x| x.strip_prefix("Basic "))
+            .and_then(|x| {
+                base64::engine::general_purpose::STANDARD
+                    .decode(x.trim())
+                    .ok()
+            })
+            .and_then(|x| String::from_utf8(x).ok());
+        let credential = match credential {
+            Some(x) => x,
+            None => return false,
+        };
+        let mut credential = credential.splitn(2, ':');
+        let user = credential.next().unwrap_or("");
+        let password = credential.next().unwrap_or("");
+
+        let hash = match self.basic_auth_users.get(user) {
+            Some(x) => x,
+            None => {
+                let _lock = BCRYPT_LOCK.lock().unwrap();
+                let _ = bcrypt::verify(password, &DUMMY_HASH);
+                return false;
+            }
+        };
+
+        // The password is not kept in the cache as is
+        let mut ctx = digest::Context::new(&digest::SHA256);
+        ctx.update(&(user.len() as u64).to_be_bytes());
+        ctx.update(user.as_bytes());
+        ctx.update(hash.as_bytes());
+        ctx.update(password.as_bytes());
+        let key = ctx.finish().as_ref().to_vec();
+        if self.auth_cache.lock().unwrap().contains(&key) {
+            return true;
+        }
+
+        let ok = {
+            let _lock = BCRYPT_LOCK.lock().unwrap();
+            bcrypt::verify(password, hash).unwrap_or(false)
+        };
+        if ok {
+            let mut cache = self.auth_cache.lock().unwrap();
+            if cache.len() >= AUTH_CACHE_SIZE {
+                let evicted = cache.iter().next().cloned();
+                if let Some(x) = evicted {
+                    cache.remove(&x);
+                }
+            }
+            cache.insert(key);
+        }
+        ok
+    }
+}
+
+fn load_certs(file: &Path) -> Result<Vec<rustls::pki_types::CertificateDer<'static>>, Error> {
+    let mut reader = BufReader::new(File::open(file)?);
+    let certs = rustls_pemfile::certs(&mut reader).collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        return Err(anyhow!("no certificate in {}", file.display()));
+    }
+    Ok(certs)
+}
+
+// -------------------------------------------------------------------------------------------------
+// Test
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::{self, Body};
+    use hyper::body::Incoming;
+    use hyper::{Request, Response, StatusCode};
+    use rcgen::{
+        BasicConstraints, CertificateParams, ExtendedKeyUsagePurpose, IsCa, Issuer, KeyPair,
+    };
+    use rustls::pki_types::ServerName;
+    use rustls::{ClientConfig, ClientConnection, StreamOwned};
+    use std::convert::TryFrom;
+    use std::env;
+    use std::fs;
+    use std::io::{self, Write};
+    use std::net::{SocketAddr, TcpStream};
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+
+    fn write_file(dir: &Path, name: &str, s: &str) {
+        let mut file = File::create(dir.join(name)).unwrap();
+        file.write_all(s.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn test_basic_auth() {
+        let dir = env::temp_dir().join("softether_exporter_basic_auth");
+        fs::create_dir_all(&dir).unwrap();
+        let hash = bcrypt::hash("secret", 4).unwrap();
+        write_file(
+            &dir,
+            "web.yml",
+            &format!("basic_auth_users:\n  alice: {}\n", hash),
+        );
+        let config = WebConfig::from_file(&dir.join("web.yml")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let basic = |x: &str| {
+            format!(
+                "Basic {}",
+                base64::engine::general_purpose::STANDARD.encode(x)
+            )
+        };
+        assert!(config.authorized(Some(&basic("alice:secret"))));
+        assert_eq!(config.auth_cache.lock().unwrap().len(), 1);
+        assert!(config.authorized(Some(&basic("alice:secret"))));
+        assert!(!config.authorized(Some(&basic("alice:wrong"))));
+        assert_eq!(config.auth_cache.lock().unwrap().len(), 1);
+        assert!(!config.authorized(Some(&basic("bob:secret"))));
+        assert!(!config.authorized(Some("Basic !!!")));
+        assert!(!config.authorized(None));
+
+        assert!(WebConfig::default().authorized(None));
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        let dir = env::temp_dir().join("softether_exporter_invalid_web_config");
+        fs::create_dir_all(&dir).unwrap();
+        write_file(&dir, "hash.yml", "basic_auth_users:\n  alice: secret\n");
+        write_file(&dir, "unknown.yml", "tls_config: {}\n");
+        write_file(
+            &dir,
+            "unsupported.yml",
+            "http_server_config:\n  http2: false\ntls_server_config:\n  cert_file: a.crt\n  key_file: a.key\n  prefer_server_cipher_suites: true\n",
+        );
+        write_file(
+            &dir,
+            "max_version.yml",
+            "tls_server_config:\n  cert_file: a.crt\n  key_file: a.key\n  max_version: TLS12\n",
+        );
+        write_file(
+            &dir,
+            "sans.yml",
+            "tls_server_config:\n  cert_file: a.crt\n  key_file: a.key\n  client_allowed_sans: [client]\n",
+        );
+        write_file(
+            &dir,
+            "request.yml",
+            "tls_server_config:\n  cert_file: a.crt\n  key_file: a.key\n  client_auth_type: RequestClientCert\n",
+        );
+        write_file(
+            &dir,
+            "ca.yml",
+            "tls_server_config:\n  cert_file: a.crt\n  key_file: a.key\n  client_auth_type: RequireAndVerifyClientCert\n",
+        );
+        assert!(WebConfig::from_file(&dir.join("hash.yml")).is_err());
+        assert!(WebConfig::from_file(&dir.join("unknown.yml")).is_err());
+        assert!(WebConfig::from_file(&dir.join("unsupported.yml")).is_ok());
+        assert!(WebConfig::from_file(&dir.join("max_version.yml")).is_err());
+        assert!(WebConfig::from_file(&dir.join("sans.yml")).is_err());
+        let ret = WebConfig::from_file(&dir.join("request.yml")).unwrap_err();
+        assert!(ret
+            .to_string()
+            .starts_with("client_auth_type RequestClientCert in "));
+        let config = WebConfig::from_file(&dir.join("ca.yml")).unwrap();
+        assert!(config.tls_acceptor().is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn generate_certs(dir: &Path) {
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::new(vec![]).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca_cert = ca_params.self_signed(&ca_key).unwrap();
+        let ca = Issuer::new(ca_params, ca_key);
+
+        let server_key = KeyPair::generate().unwrap();
+        let server_cert = CertificateParams::new(vec![String::from("localhost")])
+            .unwrap()
+            .signed_by(&server_key, &ca)
+            .unwrap();
+
+        let client_key = KeyPair::generate().unwrap();
+        let mut client_params = CertificateParams::new(vec![]).unwrap();
+        client_params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+        let client_cert = client_params.signed_by(&client_key, &ca).unwrap();
+
+        write_file(dir, "ca.crt", &ca_cert.pem());
+        write_file(dir, "server.crt", &server_cert.pem());
+        write_file(dir, "server.key", &server_key.serialize_pem());
+        write_file(dir, "client.crt", &client_cert.pem());
+        write_file(dir, "client.key", &client_key.serialize_pem());
+    }
+
+    fn get(dir: &Path, addr: SocketAddr, client_cert: bool) -> io::Result<String> {
+        let mut roots = RootCertStore::empty();
+        for cert in load_certs(&dir.join("ca.crt")).unwrap() {
+            roots.add(cert).unwrap();
+        }
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots);
+        let config = if client_cert {
+            let certs = load_certs(&dir.join("client.crt")).unwrap();
+            let key = rustls_pemfile::private_key(&mut BufReader::new(
+                File::open(dir.join("client.key")).unwrap(),
+            ))
+            .unwrap()
+            .unwrap();
+            builder.with_client_auth_cert(certs, key).unwrap()
+        } else {
+            builder.with_no_client_auth()
+        };
+
+        let name = ServerName::try_from("localhost").unwrap();
+        let conn = ClientConnection::new(Arc::new(config), name).unwrap();
+        let mut stream = StreamOwned::new(conn, TcpStream::connect(addr)?);
+        stream.write_all(b"GET /metrics HTTP/1.0\r\nHost: localhost\r\n\r\n")?;
+        let mut buf = String::new();
+        stream.read_to_string(&mut buf)?;
+        Ok(buf)
+    }
+
+    #[test]
+    fn test_tls() {
+        let dir = env::temp_dir().join("softether_exporter_tls");
+        fs::create_dir_all(&dir).unwrap();
+        generate_certs(&dir);
+        write_file(
+            &dir,
+            "web.yml",
+            "tls_server_config:\n  cert_file: server.crt\n  key_file: server.key\n  client_auth_type: RequireAndVerifyClientCert\n  client_ca_file: ca.crt\n",
+        );
+
+        let config = WebConfig::from_file(&dir.join("web.yml")).unwrap();
+        let tls = config.tls_acceptor().unwrap();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let listener = runtime.block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handler = |_: Request<Incoming>| async {
+            server::response(StatusCode::OK, "text/plain", "ok") as Response<Body>
+        };
+        runtime.spawn(server::serve(
+            listener,
+            tls,
+            Duration::from_secs(10),
+            handler,
+            std::future::pending(),
+        ));
+
+        let ret = get(&dir, addr, true).unwrap();
+        assert!(ret.starts_with("HTTP/1.0 200 OK"), "{}", ret);
+        assert!(ret.ends_with("ok"));
+
+        // Clients without certificate are rejected
+        assert!(get(&dir, addr, false).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}